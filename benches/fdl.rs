@@ -0,0 +1,46 @@
+/*
+times a stereo `UPConv` over the frequency domain delay line sizes converb's
+background segments use, run it with `cargo bench --bench fdl`
+*/
+
+use convrs::upconv::{FadeCurve, Routing, Scheme, UPConv};
+use realfft::num_complex::Complex;
+use std::{hint::black_box, time::Instant};
+
+fn main() {
+    let routing = Routing::Parallel(2);
+
+    for (block_size, num_blocks) in [(1024, 21), (8192, 23)] {
+        let filter = vec![
+            Complex {
+                re: 0.001f32,
+                im: 0.0
+            };
+            (block_size + 1) * num_blocks * 2
+        ];
+        let mut upconv = UPConv::new(
+            block_size,
+            &filter,
+            routing,
+            num_blocks,
+            FadeCurve::default(),
+            block_size,
+            Scheme::default(),
+        );
+        let input = vec![0.1f32; block_size * routing.inputs()];
+
+        // the same number of samples for every size, so they all take about as long
+        let blocks = (1 << 22) / block_size;
+        let start = Instant::now();
+        for _ in 0..blocks {
+            black_box(upconv.process_block(input.chunks_exact(block_size)));
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+
+        println!(
+            "{block_size}x{num_blocks}: {:.1} us/block, {:.1}x real time at 48k",
+            elapsed * 1e6 / blocks as f64,
+            (blocks * block_size) as f64 / 48000.0 / elapsed
+        );
+    }
+}
@@ -2,24 +2,50 @@ pub mod editor;
 pub mod long_stereo_2;
 pub mod short_2;
 
-use convrs::{conv::Conv, helpers::process_filter};
+use convrs::{
+    conv::{Conv, Scheduling},
+    filter::ProcessedFilter,
+    ir::{load_wav, IrError},
+    swap::{SwapConv, SwapHandle},
+    upconv::{FadeCurve, Routing},
+};
 
-use hound::WavReader;
 use nih_plug::prelude::*;
 use nih_plug_vizia::ViziaState;
-use num::Complex;
-use rtrb::{Consumer, RingBuffer};
-use std::sync::{Arc, Mutex};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+};
 
 struct Converb {
     params: Arc<ConverbParams>,
-    conv: Conv,
-    filter_cons: Option<Consumer<Complex<f32>>>,
-    filter_buff: Vec<Complex<f32>>,
+    conv: SwapConv,
+    // new engines are built in initialize and staged with this, and the old ones are dropped here too
+    swap: SwapHandle,
+    // filters are loaded in the background and come in here whole, with their partition
+    // and length, and the ones they replace go back to be dropped off the audio thread
+    filter_cons: Option<Consumer<ProcessedFilter>>,
+    retired_prod: Option<Producer<ProcessedFilter>>,
+    filter_buff: ProcessedFilter,
     is_filter_1: bool,
-    processed_filter_len: usize,
+    // a filter that's been read in but that conv couldn't take yet
+    filter_pending: bool,
+    // the host's sample rate, for the background thread to resample the irs to
+    sample_rate: Arc<AtomicU32>,
+    // the rate the running engine's filter was resampled to
+    engine_sample_rate: u32,
+    // offline bounces run every segment on the audio thread, so they come out the same every time
+    scheduling: Scheduling,
+    // the longest buffer the host has said it'll give us
+    max_buffer_size: usize,
 }
 
+/// how many samples the old engine is faded out over when a new one is swapped in
+const SWAP_FADE_LEN: usize = 4096;
+
+const PARTITION: &[(usize, usize)] = &[(128, 22), (1024, 21), (8192, 23)];
+
 #[derive(Params)]
 struct ConverbParams {
     #[id = "filter 1"]
@@ -34,32 +60,56 @@ enum Tasks {
     Filter2,
 }
 
+const FILTER_1: &[u8] = include_bytes!("../../tests/test_sounds/IRs/short2.wav");
+const FILTER_2: &[u8] = include_bytes!("../../tests/test_sounds/IRs/long_stereo2.wav");
+
+/// loads one of the bundled irs, ready to hand to `Conv`
+fn load_filter(
+    wav: &[u8],
+    sample_rate: u32,
+    partition: &[(usize, usize)],
+) -> Result<ProcessedFilter, IrError> {
+    let capacity = partition.iter().map(|p| p.0 * p.1).sum();
+    load_wav(wav)?
+        .map_channels(Routing::Parallel(2))?
+        .resample(sample_rate)?
+        // at high rates the long ir runs past the end of the partition
+        .truncate(capacity)
+        // so switching between them doesn't jump in level
+        .normalize(1.0)
+        .process(partition)
+}
+
 impl Default for Converb {
     fn default() -> Self {
-        let partition = &[(128, 22), (1024, 21), (8192, 23)];
-        let processed_filter_len = partition.iter().map(|p| (p.0 + 1) * p.1 * 2).sum();
-
-        // TODO use `include_bytes!` instead
-        let mut reader =
-            WavReader::new(&include_bytes!("../../tests/test_sounds/IRs/short2.wav")[..]).unwrap();
-        let filter_samples: Vec<f32> = reader
-            .samples::<i32>()
-            .map(|s| s.unwrap() as f32 / i32::MAX as f32)
-            .collect();
-        let filter_1_spectrums = process_filter(
-            vec![filter_samples.clone(), filter_samples.clone()],
-            partition,
-        );
-
-        let conv = Conv::new(128, &filter_1_spectrums, partition, 2);
+        // the host's sample rate isn't known yet, so this is at the ir's own rate
+        let filter_1_spectrums =
+            load_filter(FILTER_1, 48000, PARTITION).expect("the bundled ir can be loaded");
+
+        let conv = Conv::new(
+            128,
+            &filter_1_spectrums,
+            Routing::Parallel(2),
+            128,
+            FadeCurve::default(),
+            Scheduling::default(),
+        )
+        .expect("the default partition fits the default filter");
+        let (conv, swap) = SwapConv::new(conv, SWAP_FADE_LEN, FadeCurve::default());
 
         Self {
             params: Arc::new(ConverbParams::default()),
             conv,
-            filter_buff: vec![Complex { re: 0.0, im: 0.0 }; processed_filter_len],
+            swap,
+            filter_buff: filter_1_spectrums,
             filter_cons: None,
+            retired_prod: None,
             is_filter_1: true,
-            processed_filter_len,
+            filter_pending: false,
+            sample_rate: Arc::new(AtomicU32::new(48000)),
+            engine_sample_rate: 48000,
+            scheduling: Scheduling::default(),
+            max_buffer_size: 128,
         }
     }
 }
@@ -117,12 +167,85 @@ impl Plugin for Converb {
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
+        let sample_rate = buffer_config.sample_rate as u32;
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+
+        let scheduling = match buffer_config.process_mode {
+            ProcessMode::Offline => Scheduling::Offline,
+            _ => Scheduling::default(),
+        };
+        let max_buffer_size = buffer_config.max_buffer_size as usize;
+        if scheduling != self.scheduling
+            || max_buffer_size != self.max_buffer_size
+            || sample_rate != self.engine_sample_rate
+        {
+            // initialize isn't run on the audio thread, so a new conv can be built here,
+            // with whatever filter it was last given, and unless it's for a bounce
+            // it gets faded to once processing starts again
+            if self.filter_pending {
+                self.filter_pending = false;
+                self.is_filter_1 = self.params.filter_1.value();
+            }
+            // the ir that's playing is loaded again, resampled for the new rate
+            if self.filter_buff.sample_rate() != Some(sample_rate) {
+                let wav = if self.is_filter_1 { FILTER_1 } else { FILTER_2 };
+                match load_filter(wav, sample_rate, PARTITION) {
+                    Ok(filter) => self.filter_buff = filter,
+                    Err(e) => {
+                        nih_log!("couldn't load the filter at {sample_rate}: {e}");
+                        return false;
+                    }
+                }
+            }
+            let conv = match Conv::new(
+                128,
+                &self.filter_buff,
+                Routing::Parallel(2),
+                max_buffer_size.max(128),
+                FadeCurve::default(),
+                scheduling,
+            ) {
+                Ok(conv) => conv,
+                Err(e) => {
+                    nih_log!("couldn't build the engine: {e}");
+                    return false;
+                }
+            };
+            let replaced = if scheduling != self.scheduling {
+                // a bounce has to come out the same every time, so it can't start with a fade
+                // from the engine that was playing, and initialize never runs at the same time
+                // as process, so the engine is just replaced, along with any that's staged
+                (self.conv, self.swap) = SwapConv::new(conv, SWAP_FADE_LEN, FadeCurve::default());
+                Ok(())
+            } else {
+                self.swap.stage(conv)
+            };
+            match replaced {
+                Ok(()) => {
+                    self.scheduling = scheduling;
+                    self.max_buffer_size = max_buffer_size;
+                    self.engine_sample_rate = sample_rate;
+                }
+                // the last one hasn't been swapped in yet, so the running engine carries on,
+                // taking any longer buffers a max block at a time, and this is tried again
+                // next time
+                Err(e) => nih_log!("couldn't stage the new engine: {e}"),
+            }
+        }
+
+        context.set_latency_samples(self.conv.conv().latency() as u32);
+
         true
     }
 
+    fn reset(&mut self) {
+        // so the old tail doesn't play when the transport starts again
+        self.conv.reset();
+    }
+
     fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
         editor::create(self.params.clone(), self.params.editor_state.clone())
     }
@@ -130,80 +253,35 @@ impl Plugin for Converb {
     fn task_executor(&mut self) -> TaskExecutor<Self> {
         // this is not a practical way to do things, but it shows how you would do the filter processing
         // offline and send the new filter to the Conv
-        let partition = &[(128, 22), (1024, 21), (8192, 23)];
-        let processed_filter_len = partition.iter().map(|p| (p.0 + 1) * p.1 * 2).sum();
-
-        let (filter_prod, filter_cons) = RingBuffer::<Complex<f32>>::new(processed_filter_len * 2);
+        let (filter_prod, filter_cons) = RingBuffer::<ProcessedFilter>::new(2);
+        let (retired_prod, retired_cons) = RingBuffer::<ProcessedFilter>::new(2);
         self.filter_cons = Some(filter_cons);
-
-        let safe_prod = Arc::new(Mutex::new(filter_prod));
-
-        Box::new(move |task| match task {
-            Tasks::Filter1 => {
-                let mut reader =
-                    WavReader::new(&include_bytes!("../../tests/test_sounds/IRs/short2.wav")[..])
-                        .unwrap();
-                let filter_samples: Vec<f32> = reader
-                    .samples::<i32>()
-                    .map(|s| s.unwrap() as f32 / i32::MAX as f32)
-                    .collect();
-                let filter_1_spectrums = process_filter(
-                    vec![filter_samples.clone(), filter_samples.clone()],
-                    partition,
-                );
-
-                match safe_prod
-                    .clone()
-                    .lock()
-                    .unwrap()
-                    .write_chunk(processed_filter_len)
-                {
-                    Ok(mut w) => {
-                        let (s1, s2) = w.as_mut_slices();
-                        s1.copy_from_slice(&filter_1_spectrums[0..s1.len()]);
-                        s2.copy_from_slice(&filter_1_spectrums[s1.len()..s1.len() + s2.len()]);
-                        w.commit_all();
-                    }
-                    Err(_) => todo!(),
-                }
-            }
-            Tasks::Filter2 => {
-                let mut long_l = vec![];
-                let mut long_r = vec![];
-
-                let mut reader = WavReader::new(
-                    &include_bytes!("../../tests/test_sounds/IRs/long_stereo2.wav")[..],
-                )
-                .unwrap();
-
-                let filter_samples: Vec<f32> = reader
-                    .samples::<i32>()
-                    .map(|s| s.unwrap() as f32 / i32::MAX as f32)
-                    .collect();
-
-                for (sample, i) in filter_samples.iter().zip(0..) {
-                    if i % 2 == 0 {
-                        long_l.push(*sample);
-                    } else {
-                        long_r.push(*sample);
-                    }
-                }
-                let filter_2_spectrums = process_filter(vec![long_l, long_r], partition);
-
-                match safe_prod
-                    .clone()
-                    .lock()
-                    .unwrap()
-                    .write_chunk(processed_filter_len)
-                {
-                    Ok(mut w) => {
-                        let (s1, s2) = w.as_mut_slices();
-                        s1.copy_from_slice(&filter_2_spectrums[0..s1.len()]);
-                        s2.copy_from_slice(&filter_2_spectrums[s1.len()..s1.len() + s2.len()]);
-                        w.commit_all();
-                    }
-                    Err(_) => todo!(),
+        self.retired_prod = Some(retired_prod);
+
+        let queues = Arc::new(Mutex::new((filter_prod, retired_cons)));
+        let sample_rate = self.sample_rate.clone();
+
+        Box::new(move |task| {
+            let (wav, name) = match task {
+                Tasks::Filter1 => (FILTER_1, "filter 1"),
+                Tasks::Filter2 => (FILTER_2, "filter 2"),
+            };
+            let filter = match load_filter(wav, sample_rate.load(Ordering::Relaxed), PARTITION) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    nih_log!("couldn't load {name}: {e}");
+                    return;
                 }
+            };
+
+            let mut queues = queues.lock().unwrap();
+            let (filter_prod, retired_cons) = &mut *queues;
+            // the filters the audio thread is done with are dropped here
+            while retired_cons.pop().is_ok() {}
+            // if the audio thread hasn't picked up the last filters yet,
+            // this one gets dropped, the switch will be asked for again
+            if filter_prod.push(filter).is_err() {
+                nih_log!("filter queue is full, dropping {name}");
             }
         })
     }
@@ -222,38 +300,43 @@ impl Plugin for Converb {
             }
         }
 
-        if !self.filter_cons.as_ref().unwrap().is_empty() {
-            match self
-                .filter_cons
-                .as_mut()
-                .unwrap()
-                .read_chunk(self.processed_filter_len)
-            {
-                Ok(r) => {
-                    let (s1, s2) = r.as_slices();
-                    self.filter_buff[0..s1.len()].copy_from_slice(s1);
-                    self.filter_buff[s1.len()..s1.len() + s2.len()].copy_from_slice(s2);
-                    r.commit_all();
-                }
-                Err(_) => todo!(),
+        // the filter a new one replaces has to go back to be dropped, so one is
+        // only taken when there's room to send the old one back
+        if !self.filter_pending && self.retired_prod.as_ref().is_some_and(|r| !r.is_full()) {
+            if let Some(filter) = self.filter_cons.as_mut().and_then(|c| c.pop().ok()) {
+                // a filter loaded before the sample rate changed goes straight back,
+                // and the switch is asked for again at the new rate
+                let old = if filter.sample_rate() == self.filter_buff.sample_rate() {
+                    self.filter_pending = true;
+                    std::mem::replace(&mut self.filter_buff, filter)
+                } else {
+                    filter
+                };
+                let _ = self.retired_prod.as_mut().unwrap().push(old);
             }
+        }
 
-            self.conv.update_filter(&self.filter_buff);
-
+        // if the workers haven't taken the last update yet, or it's still fading in,
+        // we just hang on to this one and try again next time
+        if self.filter_pending
+            && self
+                .conv
+                .conv_mut()
+                .update_filter(&self.filter_buff)
+                .is_ok()
+        {
+            self.filter_pending = false;
             self.is_filter_1 = self.params.filter_1.value();
         }
 
-        for (_size, mut block) in buffer.iter_blocks(128) {
-            let map = block.iter_mut().map(|b| &*b);
-            let out = self.conv.process_block(map.into_iter());
-            for (b, o) in block.iter_mut().zip(out) {
-                for (bb, oo) in b.iter_mut().zip(o) {
-                    *bb = *oo;
-                }
+        for (_size, mut block) in buffer.iter_blocks(self.conv.conv().max_block_size()) {
+            let mut channels = block.iter_mut();
+            if let (Some(l), Some(r)) = (channels.next(), channels.next()) {
+                self.conv.process_in_place(&mut [l, r]);
             }
         }
 
-        ProcessStatus::Normal
+        ProcessStatus::Tail(self.conv.conv().tail_len() as u32)
     }
 }
 
@@ -1,137 +1,832 @@
 use realfft::num_complex::Complex;
-use std::thread;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
 
-use crate::upconv::UPConv;
-use rtrb::{Consumer, Producer, RingBuffer};
+use crate::{
+    filter::{check_fft_ratios, ProcessedFilter},
+    fir::Fir,
+    helpers::process_filter_padded,
+    partition::{self, Constraints},
+    queue::{block_queue, BlockConsumer, BlockProducer},
+    sample::Sample,
+    upconv::{FadeCurve, Overlap, Routing, Scheme, UPConv},
+};
 
 /*
 TODO
-- need to be able to read and write straight slices from ringbuf, so probably a dedicated filter struct
 - this may lead us to wanting to move away from vecs, which we want to do eventually anyway
 */
 
-pub struct Conv {
-    rt_segment: UPConv,
-    non_rt_segments: Vec<SegmentHandle>,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConvError {
+    /// there has to be at least one channel
+    NoChannels,
+    /// block sizes have to be at least one sample
+    ZeroBlockSize,
+    EmptyPartition,
+    /// the first segment is run in the audio callback,
+    /// so its block size has to be the block size `Conv` is run with
+    BlockSizeMismatch {
+        block_size: usize,
+        first_segment: usize,
+    },
+    /// every segment needs at least one block
+    EmptySegment {
+        segment: usize,
+    },
+    /// segment block sizes have to be power of two multiples of the block size,
+    /// and can't get smaller from one segment to the next
+    InvalidSegmentSize {
+        segment: usize,
+        size: usize,
+    },
+    /// a segment has to start further into the filter than its block size,
+    /// otherwise its output is needed before its input has been collected
+    SegmentTooEarly {
+        segment: usize,
+        offset: usize,
+        size: usize,
+    },
+    /// the filter doesn't have the layout `process_filter` gives for this partition and channel count
+    FilterLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// there has to be one filter per path of the routing
+    FilterCount {
+        expected: usize,
+        actual: usize,
+    },
+    /// the filter, or the capacity asked for, is longer than the partition covers
+    FilterTooLong {
+        len: usize,
+        capacity: usize,
+    },
+    /// a processed filter can only be used with the partition and fft sizes it was processed
+    /// for, or with a longer partition it's a `partition::cover` of
+    PartitionMismatch,
+    /// there has to be one scheme per segment of the partition
+    SchemeCount {
+        expected: usize,
+        actual: usize,
+    },
+    /// a segment's fft has to be at least twice its block size
+    InvalidFftRatio {
+        segment: usize,
+        ratio: usize,
+    },
+    /// the planner has to give segments at least one block of headroom
+    NoHeadroom,
+    /// a worker hasn't picked up the last filter update yet
+    FilterQueueFull,
+    /// the last filter update hasn't finished fading in yet
+    FadePending,
+    /// the last engine staged with a `SwapHandle` hasn't been swapped in yet
+    SwapPending,
+    /// an engine staged with a `SwapHandle` has to have the same latency as the one
+    /// it fades from, or the fade would be between two different points in the signal
+    LatencyMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// the head has to cover the `block_size` samples of latency of the rest of the filter
+    HeadTooShort {
+        head_len: usize,
+        block_size: usize,
+    },
+    /// a head update has to have as many taps as the head that was set,
+    /// and there has to be a head set in the first place
+    HeadLength {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for ConvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoChannels => write!(f, "conv needs at least one channel"),
+            Self::ZeroBlockSize => write!(f, "block sizes have to be at least one sample"),
+            Self::EmptyPartition => write!(f, "partition has no segments"),
+            Self::BlockSizeMismatch {
+                block_size,
+                first_segment,
+            } => write!(
+                f,
+                "first segment has block size {first_segment}, but the block size is {block_size}"
+            ),
+            Self::EmptySegment { segment } => write!(f, "segment {segment} has no blocks"),
+            Self::InvalidSegmentSize { segment, size } => write!(
+                f,
+                "segment {segment} has block size {size}, which isn't a power of two multiple of \
+                the block size at least as big as the segment before it"
+            ),
+            Self::SegmentTooEarly {
+                segment,
+                offset,
+                size,
+            } => write!(
+                f,
+                "segment {segment} starts {offset} samples into the filter, \
+                which isn't more than its block size of {size}"
+            ),
+            Self::FilterLength { expected, actual } => write!(
+                f,
+                "filter has {actual} bins, but the partition needs {expected}"
+            ),
+            Self::FilterCount { expected, actual } => write!(
+                f,
+                "there are {actual} filters, but the routing has {expected} paths"
+            ),
+            Self::FilterTooLong { len, capacity } => write!(
+                f,
+                "filter is {len} samples long, but the partition only covers {capacity}"
+            ),
+            Self::PartitionMismatch => {
+                write!(f, "filter was processed for a different partition")
+            }
+            Self::SchemeCount { expected, actual } => write!(
+                f,
+                "there are {actual} schemes, but the partition has {expected} segments"
+            ),
+            Self::InvalidFftRatio { segment, ratio } => write!(
+                f,
+                "segment {segment} has an fft ratio of {ratio}, but it has to be at least 2"
+            ),
+            Self::NoHeadroom => write!(f, "the planner needs at least one block of headroom"),
+            Self::FilterQueueFull => write!(f, "the last filter update hasn't been picked up yet"),
+            Self::FadePending => write!(f, "the last filter update is still fading in"),
+            Self::SwapPending => write!(f, "the last engine staged hasn't been swapped in yet"),
+            Self::LatencyMismatch { expected, actual } => write!(
+                f,
+                "engine has {actual} samples of latency, but the one it replaces has {expected}"
+            ),
+            Self::HeadTooShort {
+                head_len,
+                block_size,
+            } => write!(
+                f,
+                "head has {head_len} taps, but it has to cover the block size of {block_size}"
+            ),
+            Self::HeadLength { expected, actual } => {
+                write!(f, "head has {actual} taps, but it needs {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConvError {}
+
+/// what a background segment plays when its worker hasn't got a block done in time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissPolicy {
+    /// the segment is silent for the block
+    #[default]
+    Silence,
+    /// the last block the segment produced is played again
+    Hold,
+    /// the last block the segment produced is faded out over the block,
+    /// and the segment is silent after that until it catches up
+    Fade,
+}
+
+/// where the background segments do their work
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scheduling {
+    /// every segment gets a worker thread of its own
+    #[default]
+    Threaded,
+    /// segments are run on the audio thread, with the work for each block split
+    /// into steps that are spread evenly over the cycles before it's due,
+    /// so every callback does about the same amount of work
+    Distributed,
+    /// segments are run on the audio thread, with all the work for a block done
+    /// as soon as it's sent, so nothing depends on timing and the output is exactly
+    /// what the threaded mode puts out when its workers keep up
+    ///
+    /// some callbacks do a lot more work than others, so this is for offline
+    /// rendering and tests, rather than real time
+    Offline,
+}
+
+/// counts the blocks each segment has missed, this can be handed to other
+/// threads (like a gui) to keep an eye on how the workers are keeping up
+pub struct DeadlineMonitor {
+    missed: Vec<AtomicUsize>,
+}
+
+impl DeadlineMonitor {
+    /// segments are indexed the same way as the partition,
+    /// so segment 0 is the one in the audio callback, which can't miss
+    pub fn missed(&self, segment: usize) -> usize {
+        self.missed
+            .get(segment)
+            .map_or(0, |m| m.load(Ordering::Relaxed))
+    }
+
+    pub fn total(&self) -> usize {
+        self.missed.iter().map(|m| m.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn segments(&self) -> usize {
+        self.missed.len()
+    }
+}
+
+/// everything about a `Conv` apart from its filter, which gets checked
+/// before anything is built
+#[derive(Clone, Debug)]
+pub struct ConvBuilder {
+    block_size: usize,
+    routing: Routing,
+    // if there's no partition, one gets planned for the filter
+    partition: Option<Vec<(usize, usize)>>,
+    // every segment uses the default scheme if these aren't set
+    schemes: Option<Vec<Scheme>>,
+    // the longest filter the `Conv` has to be able to take, if it's longer than the one it's built with
+    max_filter_len: Option<usize>,
+    constraints: Constraints,
+    max_block_size: Option<usize>,
+    scheduling: Scheduling,
+    miss_policy: MissPolicy,
+    fade_curve: FadeCurve,
+    fade_len: Option<usize>,
+}
+
+impl ConvBuilder {
+    /// a stereo `Conv` with a planned partition and nothing else set
+    pub fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            routing: Routing::Parallel(2),
+            partition: None,
+            schemes: None,
+            max_filter_len: None,
+            constraints: Constraints::default(),
+            max_block_size: None,
+            scheduling: Scheduling::default(),
+            miss_policy: MissPolicy::default(),
+            fade_curve: FadeCurve::default(),
+            fade_len: None,
+        }
+    }
+
+    /// one filter per channel, same as `Routing::Parallel(channels)`
+    pub fn channels(self, channels: usize) -> Self {
+        self.routing(Routing::Parallel(channels))
+    }
+
+    pub fn routing(mut self, routing: Routing) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    pub fn partition(mut self, partition: &[(usize, usize)]) -> Self {
+        self.partition = Some(Vec::from(partition));
+        self
+    }
+
+    /// how each segment does its convolution, with one scheme per segment of the partition
+    pub fn schemes(mut self, schemes: &[Scheme]) -> Self {
+        self.schemes = Some(Vec::from(schemes));
+        self
+    }
+
+    /// makes room for filters up to `max_filter_len` samples long, so longer filters than the
+    /// one it's built with can be swapped in later, the planner plans for this length, and a
+    /// partition that's been set has to cover it. shorter filters only cost what they fill
+    pub fn max_filter_len(mut self, max_filter_len: usize) -> Self {
+        self.max_filter_len = Some(max_filter_len);
+        self
+    }
+
+    /// plans the partition for the filter it gets built with, instead of using a fixed one
+    pub fn planner(mut self, constraints: Constraints) -> Self {
+        self.partition = None;
+        self.constraints = constraints;
+        self
+    }
+
+    /// the longest block `process` will be given, this defaults to the block size
+    pub fn max_block_size(mut self, max_block_size: usize) -> Self {
+        self.max_block_size = Some(max_block_size);
+        self
+    }
+
+    pub fn scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
+
+    pub fn miss_policy(mut self, miss_policy: MissPolicy) -> Self {
+        self.miss_policy = miss_policy;
+        self
+    }
+
+    pub fn fade_curve(mut self, fade_curve: FadeCurve) -> Self {
+        self.fade_curve = fade_curve;
+        self
+    }
+
+    /// how many samples filter updates are crossfaded over, if this isn't set
+    /// they're faded over one block
+    pub fn fade_len(mut self, fade_len: usize) -> Self {
+        self.fade_len = Some(fade_len);
+        self
+    }
+
+    /// checks everything that doesn't depend on the filter
+    pub fn validate(&self) -> Result<(), ConvError> {
+        if self.block_size == 0 || self.max_block_size == Some(0) {
+            return Err(ConvError::ZeroBlockSize);
+        }
+        if self.routing.inputs() == 0 || self.routing.outputs() == 0 {
+            return Err(ConvError::NoChannels);
+        }
+        if let Some(schemes) = &self.schemes {
+            let segments = self.partition.as_ref().map_or(schemes.len(), |p| p.len());
+            check_fft_ratios(&self.fft_ratios(segments), segments)?;
+        }
+        if let (Some(partition), Some(len)) = (&self.partition, self.max_filter_len) {
+            let capacity = partition.iter().map(|p| p.0 * p.1).sum();
+            if len > capacity {
+                return Err(ConvError::FilterTooLong { len, capacity });
+            }
+        }
+        match &self.partition {
+            Some(partition) => partition::validate(partition, self.block_size),
+            None if self.constraints.headroom == 0 => Err(ConvError::NoHeadroom),
+            None => Ok(()),
+        }
+    }
+
+    /// the fft ratio of every segment, from the schemes if they've been set
+    fn fft_ratios(&self, segments: usize) -> Vec<usize> {
+        match &self.schemes {
+            Some(schemes) => schemes.iter().map(|s| s.fft_ratio).collect(),
+            None => vec![2; segments],
+        }
+    }
+
+    /// `filter` has one time domain filter per path of the routing, which don't have
+    /// to be the same length, but can't be longer than the partition if one was set
+    ///
+    /// only as much of the partition as the filter needs gets processed,
+    /// the rest is left empty until a longer filter comes along
+    ///
+    /// this function is not real time safe
+    pub fn build<T: Sample>(&self, filter: Vec<Vec<T>>) -> Result<Conv<T>, ConvError> {
+        self.validate()?;
+
+        if filter.len() != self.routing.paths() {
+            return Err(ConvError::FilterCount {
+                expected: self.routing.paths(),
+                actual: filter.len(),
+            });
+        }
+
+        let len = filter.iter().map(|f| f.len()).max().unwrap_or(0);
+        let partition = match &self.partition {
+            Some(partition) => {
+                let capacity = partition.iter().map(|p| p.0 * p.1).sum();
+                if len > capacity {
+                    return Err(ConvError::FilterTooLong { len, capacity });
+                }
+                partition.clone()
+            }
+            None => partition::plan(
+                len.max(self.max_filter_len.unwrap_or(0)),
+                self.block_size,
+                self.routing.paths(),
+                &self.constraints,
+            )?,
+        };
+
+        // a planned partition might not have as many segments as there are schemes
+        let fft_ratios = self.fft_ratios(partition.len());
+        check_fft_ratios(&fft_ratios, partition.len())?;
+        let covered = partition::cover(&partition, len);
+        let filter = process_filter_padded(filter, &covered, &fft_ratios[0..covered.len()])?;
+        Ok(Conv::from_builder(self, &partition, &fft_ratios, &filter))
+    }
+
+    /// `filter` needs one channel per path of the routing, and its partition is used
+    /// for the `Conv`, so if a partition has been set as well it has to match, or
+    /// cover the start of it, which is how a capacity is set for a processed filter
+    ///
+    /// this function is not real time safe
+    pub fn build_processed<T: Sample>(
+        &self,
+        filter: &ProcessedFilter<T>,
+    ) -> Result<Conv<T>, ConvError> {
+        let partition = self.partition.as_deref().unwrap_or(filter.partition());
+        let fft_ratios = match &self.schemes {
+            Some(_) => {
+                let fft_ratios = self.fft_ratios(partition.len());
+                check_fft_ratios(&fft_ratios, partition.len())?;
+                fft_ratios
+            }
+            // the filter's fft sizes are used if the schemes aren't set,
+            // and any segments it doesn't reach get the default
+            None => {
+                let mut fft_ratios = Vec::from(filter.fft_ratios());
+                fft_ratios.resize(partition.len().max(fft_ratios.len()), 2);
+                fft_ratios
+            }
+        };
+        check_filter(filter, partition, &fft_ratios, self.routing)?;
+        self.validate()?;
+        partition::validate(partition, self.block_size)?;
+
+        Ok(Conv::from_builder(self, partition, &fft_ratios, filter))
+    }
+}
+
+/// a partitioned convolution, with the first segment run on the audio thread
+/// and the rest in the background
+///
+/// the output is at unity gain, so it's the exact convolution of the input with the
+/// filter, whatever the partition is, every segment takes its own fft size off its output
+pub struct Conv<T: Sample = f32> {
+    rt_segment: UPConv<T>,
+    non_rt_segments: Vec<SegmentHandle<T>>,
     buff_len: usize,
-    input_buff: Vec<f32>,
-    output_buff: Vec<f32>,
+    input_buff: Vec<T>,
+    output_buff: Vec<T>,
     cycle_count: usize,
     block_size: usize,
     partition: Vec<(usize, usize)>,
-    channels: usize,
+    fft_ratios: Vec<usize>,
+    routing: Routing,
+    max_block_size: usize,
+    host_in: Vec<T>,
+    host_out: Vec<T>,
+    fifo_in: Vec<T>,
+    fifo_out: Vec<T>,
+    fifo_pos: usize,
+    // the head runs one channel per path, so its output goes through here
+    // before being summed into the outputs
+    head: Option<Fir<T>>,
+    head_out: Vec<T>,
+    fade_curve: FadeCurve,
+    fade_len: Option<usize>,
+    // with overlap add a segment's new filter has to start a block before its fade,
+    // so its tail has built up by then, this is that block, or 0, for every segment
+    add_tails: Vec<usize>,
+    // the output sample the last filter update is done fading in at
+    fade_end: usize,
+    miss_policy: MissPolicy,
+    deadline_monitor: Arc<DeadlineMonitor>,
+    // how long the filter was before it was processed, and the one it's fading from
+    source_len: usize,
+    old_source_len: usize,
 }
 
-struct SegmentHandle {
+struct SegmentHandle<T: Sample> {
     block_size: usize,
     offset: usize,
     avail: usize,
-    rt_prod: Producer<f32>,
-    rt_cons: Consumer<f32>,
-    filter_prod: Producer<Complex<f32>>,
-    partition: (usize, usize),
+    engine: Engine<T>,
+    rt_cons: BlockConsumer<T>,
+    // the upconv is off on the worker thread, so we count it up before it goes
+    upconv_memory: usize,
+    // blocks are tagged with how many came before them,
+    // so we can tell which block we're looking at when we read one back
+    next_in: usize,
+    next_out: usize,
+    // the last block we got back, for when the next one is late,
+    // and whether there's anything in it worth playing
+    hold: Vec<T>,
+    held: bool,
+    // how many of the next blocks due are counted as missed whether they're back or not
+    forced_misses: usize,
+}
+
+enum Engine<T: Sample> {
+    Worker {
+        rt_prod: BlockProducer<T>,
+        // filter updates are tagged with their length, and the sample of
+        // the segment's output their fade starts at
+        filter_prod: BlockProducer<Complex<T>, (usize, usize)>,
+        worker: Option<JoinHandle<()>>,
+        running: Arc<AtomicBool>,
+        // one more than the first tag the worker should reset before, 0 if it never should
+        reset_at: Arc<AtomicUsize>,
+    },
+    Distributed(Box<DistributedSegment<T>>),
+}
+
+/// a segment that runs on the audio thread a few tasks at a time
+struct DistributedSegment<T: Sample> {
+    upconv: UPConv<T>,
+    seg_prod: BlockProducer<T>,
+    // filter updates wait here until the next block starts,
+    // so a block never gets some of its work done with each filter,
+    // with how much of the buffer the update fills, and where its fade starts
+    pending_filter: (Option<(usize, usize)>, Vec<Complex<T>>),
+    // the tag of the block being worked on, the cycle it started, and the next task to run
+    job: Option<(usize, usize, usize)>,
+    // how many cycles the work for a block is spread over
+    window: usize,
+}
+
+impl<T: Sample> SegmentHandle<T> {
+    /// the cycle the first block sent after `cycle_count` starts coming out at
+    fn first_out(&self, cycle_count: usize) -> usize {
+        let sent = (cycle_count / self.avail + 1) * self.avail;
+        sent + self.offset - self.avail
+    }
+
+    /// wakes the worker up if it's parked, this never blocks so it's fine on the audio thread
+    fn wake(&self) {
+        if let Engine::Worker {
+            worker: Some(worker),
+            ..
+        } = &self.engine
+        {
+            worker.thread().unpark();
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        let engine = match &self.engine {
+            Engine::Worker {
+                rt_prod,
+                filter_prod,
+                ..
+            } => rt_prod.memory_usage() + filter_prod.memory_usage(),
+            // the output queue is the one `rt_cons` reads from
+            Engine::Distributed(segment) => {
+                segment.pending_filter.1.len() * std::mem::size_of::<Complex<T>>()
+            }
+        };
+
+        self.upconv_memory
+            + engine
+            + self.rt_cons.memory_usage()
+            + self.hold.len() * std::mem::size_of::<T>()
+    }
+
+    fn has_room_for_filter(&self) -> bool {
+        match &self.engine {
+            Engine::Worker { filter_prod, .. } => filter_prod.has_room(),
+            Engine::Distributed(_) => true,
+        }
+    }
+
+    /// `fade_start` is counted in samples of the segment's output from its first block,
+    /// so it lands in the same place however far behind the segment is
+    fn queue_filter(&mut self, filter: &[Complex<T>], fade_start: usize) -> Result<(), ConvError> {
+        match &mut self.engine {
+            // the filter can be shorter than a block
+            Engine::Worker { filter_prod, .. } => {
+                filter_prod
+                    .push((filter.len(), fade_start), std::iter::once(filter))
+                    .map_err(|_| ConvError::FilterQueueFull)?;
+                self.wake();
+            }
+            Engine::Distributed(segment) => {
+                segment.pending_filter.1[0..filter.len()].copy_from_slice(filter);
+                segment.pending_filter.0 = Some((filter.len(), fade_start));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// hands the segment its next block of input
+    fn send<'a>(&mut self, tag: usize, channel_blocks: impl Iterator<Item = &'a [T]>) {
+        let block_size = self.block_size;
+        match &mut self.engine {
+            Engine::Worker { rt_prod, .. } => {
+                // if the worker has fallen so far behind that its queue is full, the block
+                // is dropped, and the segment will be silent when its output is due
+                let _ = rt_prod.push(tag, channel_blocks);
+                self.wake();
+            }
+            Engine::Distributed(segment) => {
+                // the window is never longer than a block, so this is only
+                // ever a safety net
+                segment.finish();
+
+                // the update waits for the last one to finish fading in,
+                // which it always has unless it's been held up somehow
+                if let Some((len, fade_start)) = segment.pending_filter.0 {
+                    if segment
+                        .upconv
+                        .update_filter(
+                            &segment.pending_filter.1[0..len],
+                            fade_start.saturating_sub(tag * block_size),
+                        )
+                        .is_ok()
+                    {
+                        segment.pending_filter.0 = None;
+                    }
+                }
+
+                segment.upconv.load_block(channel_blocks);
+                segment.job = Some((tag, 0, 0));
+            }
+        }
+    }
+
+    /// runs whatever work is due this cycle, if the segment is run on the audio thread
+    fn run_due(&mut self) {
+        if let Engine::Distributed(segment) = &mut self.engine {
+            let tasks = segment.upconv.tasks();
+
+            if let Some((tag, elapsed, mut task)) = segment.job {
+                // task t is due by cycle t * window / tasks of the job,
+                // so the last one is always done by the end of the window
+                while task < tasks && task * segment.window / tasks <= elapsed {
+                    segment.upconv.run_task(task);
+                    task += 1;
+                }
+
+                if task == tasks {
+                    let out = segment.upconv.finish_block();
+                    let _ = segment.seg_prod.push(tag, std::iter::once(out));
+                    segment.job = None;
+                } else {
+                    segment.job = Some((tag, elapsed + 1, task));
+                }
+            }
+        }
+    }
 }
 
-impl Conv {
+impl<T: Sample> DistributedSegment<T> {
+    /// runs whatever is left of the current block all at once
+    fn finish(&mut self) {
+        if let Some((tag, _, task)) = self.job.take() {
+            for t in task..self.upconv.tasks() {
+                self.upconv.run_task(t);
+            }
+
+            let out = self.upconv.finish_block();
+            let _ = self.seg_prod.push(tag, std::iter::once(out));
+        }
+    }
+}
+
+impl<T: Sample> Conv<T> {
+    /// `starting_filter` needs one channel per path of `routing`,
+    /// and the `Conv` uses the partition it was processed for
+    ///
+    /// this function is not real time safe
     pub fn new(
         block_size: usize,
-        starting_filter: &[Complex<f32>],
+        starting_filter: &ProcessedFilter<T>,
+        routing: Routing,
+        max_block_size: usize,
+        fade_curve: FadeCurve,
+        scheduling: Scheduling,
+    ) -> Result<Self, ConvError> {
+        ConvBuilder::new(block_size)
+            .routing(routing)
+            .max_block_size(max_block_size)
+            .fade_curve(fade_curve)
+            .scheduling(scheduling)
+            .build_processed(starting_filter)
+    }
+
+    /// everything has been checked by the builder by the time we get here,
+    /// `starting_filter` can be for just the start of `partition`
+    fn from_builder(
+        builder: &ConvBuilder,
         partition: &[(usize, usize)],
-        channels: usize,
+        fft_ratios: &[usize],
+        starting_filter: &ProcessedFilter<T>,
     ) -> Self {
-        let mut filter_index = 0;
-        let first_part = &starting_filter[0..(partition[0].0 + 1) * partition[0].1 * channels];
+        let (block_size, routing, fade_curve, scheduling) = (
+            builder.block_size,
+            builder.routing,
+            builder.fade_curve,
+            builder.scheduling,
+        );
+        // every segment fades over the same number of samples
+        let fade_len = builder.fade_len.unwrap_or(block_size);
+        let max_block_size = builder.max_block_size.unwrap_or(block_size);
+        let (inputs, outputs) = (routing.inputs(), routing.outputs());
 
-        let rt_segment = UPConv::new(partition[0].0, first_part, channels, partition[0].1);
+        // the fft sizes have been checked against the filter's
+        let schemes: Vec<Scheme> = fft_ratios
+            .iter()
+            .enumerate()
+            .map(|(i, fft_ratio)| Scheme {
+                overlap: builder
+                    .schemes
+                    .as_ref()
+                    .map_or(Overlap::Save, |s| s[i].overlap),
+                fft_ratio: *fft_ratio,
+            })
+            .collect();
 
-        filter_index += (partition[0].0 + 1) * partition[0].1 * channels;
+        let rt_segment = UPConv::new(
+            partition[0].0,
+            starting_filter.segment(0),
+            routing,
+            partition[0].1,
+            fade_curve,
+            fade_len,
+            schemes[0],
+        );
 
         let mut non_rt_segments = vec![];
-        if partition.len() > 1 {
-            let mut offset_samples = partition[0].0 * partition[0].1;
-            for i in 1..partition.len() {
-                let p = partition[i];
-                // TODO figure out the correct ringbuf length based on the offset
-                let (rt_prod, mut seg_cons) = RingBuffer::<f32>::new(p.0 * channels * 1000);
-                let (mut seg_prod, rt_cons) = RingBuffer::<f32>::new(p.0 * channels * 1000);
-
-                let (filter_prod, mut filter_cons) =
-                    RingBuffer::<Complex<f32>>::new((p.0 + 1) * p.1 * channels * 2);
-
-                let mut upconv = UPConv::new(
-                    p.0,
-                    &starting_filter[filter_index..filter_index + ((p.0 + 1) * p.1 * channels)],
-                    channels,
-                    p.1,
-                );
+        let mut offset_samples = partition[0].0 * partition[0].1;
+        for (i, p) in partition.iter().copied().enumerate().skip(1) {
+            let seg_filter = segment_or_empty(starting_filter, i);
+            // the buffers for filter updates have room for the whole segment
+            let seg_filter_len = (p.0 * schemes[i].fft_ratio / 2 + 1) * p.1 * routing.paths();
 
-                filter_index += ((p.0 + 1) * p.1) * channels;
+            let upconv = UPConv::new(
+                p.0, seg_filter, routing, p.1, fade_curve, fade_len, schemes[i],
+            );
 
-                thread::spawn(move || {
-                    // TODO a raw loop i feel like is a really bad idea here
-                    // so consider this pseudocode for now
-                    loop {
-                        if !filter_cons.is_empty() {
-                            match filter_cons.read_chunk((p.0 + 1) * p.1 * channels) {
-                                Ok(r) => {
-                                    let (s1, s2) = r.as_slices();
+            let upconv_memory = upconv.memory_usage();
+            let avail = p.0 / block_size;
+            let offset = offset_samples / block_size;
 
-                                    upconv.update_filter(&[s1, s2].concat());
+            let (engine, rt_cons) = match scheduling {
+                // a block is due `offset - avail` cycles after it's sent, so that's as many
+                // as can be in flight, and any more than that would be late anyway
+                Scheduling::Threaded => spawn_worker(
+                    upconv,
+                    p.0,
+                    routing,
+                    seg_filter_len,
+                    (offset - avail) / avail + 2,
+                ),
+                Scheduling::Distributed | Scheduling::Offline => {
+                    // blocks are done by the end of their window, and sit in
+                    // the queue until they're due
+                    let (seg_prod, rt_cons) =
+                        block_queue::<T, _>(p.0 * outputs, (offset - avail) / avail + 2);
 
-                                    r.commit_all();
-                                }
-                                Err(_) => todo!(),
-                            }
-                        }
-                        if !seg_cons.is_empty() {
-                            match seg_cons.read_chunk(p.0 * channels) {
-                                Ok(r) => {
-                                    let (s1, s2) = r.as_slices();
-
-                                    let out =
-                                        upconv.process_block([s1, s2].concat().chunks_exact(p.0));
-
-                                    match seg_prod.write_chunk(p.0 * channels) {
-                                        Ok(mut w) => {
-                                            let (w1, w2) = w.as_mut_slices();
-
-                                            w1.copy_from_slice(&out[0..w1.len()]);
-                                            w2.copy_from_slice(&out[w1.len()..w1.len() + w2.len()]);
-
-                                            w.commit_all();
-                                        }
-                                        Err(_) => todo!(),
-                                    }
-
-                                    r.commit_all();
-                                }
-                                Err(_) => todo!(),
-                            }
-                        }
-                    }
-                });
+                    let segment = DistributedSegment {
+                        upconv,
+                        seg_prod,
+                        pending_filter: (
+                            None,
+                            vec![
+                                Complex {
+                                    re: T::zero(),
+                                    im: T::zero()
+                                };
+                                seg_filter_len
+                            ],
+                        ),
+                        job: None,
+                        // with no window every task is due in the cycle the block is sent
+                        window: match scheduling {
+                            Scheduling::Offline => 0,
+                            _ => (offset - avail).min(avail),
+                        },
+                    };
 
-                non_rt_segments.push(SegmentHandle {
-                    avail: p.0 / block_size,
-                    offset: offset_samples / block_size,
-                    block_size: p.0,
-                    rt_prod,
-                    rt_cons,
-                    filter_prod,
-                    partition: p,
-                });
+                    (Engine::Distributed(Box::new(segment)), rt_cons)
+                }
+            };
 
-                offset_samples += p.0 * p.1;
-            }
+            non_rt_segments.push(SegmentHandle {
+                avail,
+                offset,
+                block_size: p.0,
+                engine,
+                rt_cons,
+                next_in: 0,
+                next_out: 0,
+                upconv_memory,
+                hold: vec![T::zero(); p.0 * outputs],
+                held: false,
+                forced_misses: 0,
+            });
+
+            offset_samples += p.0 * p.1;
         }
 
         // TODO this might be more buffer than we need,
         // we might need just the last block size plus the main block size
-        let input_buff = vec![0.0; partition.last().unwrap().0 * channels];
-        let output_buff = vec![0.0; partition.last().unwrap().0 * 2 * channels];
+        let input_buff = vec![T::zero(); partition.last().unwrap().0 * inputs];
+        let output_buff = vec![T::zero(); partition.last().unwrap().0 * 2 * outputs];
 
         let buff_len = partition.last().unwrap().0;
 
+        let add_tails = schemes
+            .iter()
+            .zip(partition)
+            .map(|(scheme, p)| match scheme.overlap {
+                Overlap::Save => 0,
+                Overlap::Add => p.0,
+            })
+            .collect();
+
         Self {
             rt_segment,
             input_buff,
@@ -141,162 +836,589 @@ impl Conv {
             block_size,
             buff_len,
             partition: Vec::from(partition),
-            channels,
+            fft_ratios: Vec::from(fft_ratios),
+            routing,
+            max_block_size,
+            host_in: vec![T::zero(); max_block_size * inputs],
+            host_out: vec![T::zero(); max_block_size * outputs],
+            fifo_in: vec![T::zero(); block_size * inputs],
+            fifo_out: vec![T::zero(); block_size * outputs],
+            fifo_pos: 0,
+            head: None,
+            head_out: vec![],
+            fade_curve,
+            fade_len: builder.fade_len,
+            add_tails,
+            fade_end: 0,
+            miss_policy: builder.miss_policy,
+            source_len: starting_filter.source_len(),
+            old_source_len: 0,
+            deadline_monitor: Arc::new(DeadlineMonitor {
+                missed: partition.iter().map(|_| AtomicUsize::new(0)).collect(),
+            }),
+        }
+    }
+
+    /// runs the first taps of the filter directly in `process`, with no latency at all,
+    /// `head` is every path's taps one after the other, and the filter the `Conv`
+    /// runs has to be the tail that goes with it, `helpers::split_head` gives you both
+    ///
+    /// this function is not real time safe
+    pub fn set_head(&mut self, head: &[T]) -> Result<(), ConvError> {
+        let paths = self.routing.paths();
+        let head_len = head.len() / paths;
+        if head_len < self.block_size {
+            return Err(ConvError::HeadTooShort {
+                head_len,
+                block_size: self.block_size,
+            });
+        }
+        if head.len() != head_len * paths {
+            return Err(ConvError::HeadLength {
+                expected: head_len * paths,
+                actual: head.len(),
+            });
+        }
+
+        // with a head `process_block` goes through `process`,
+        // so it has to be able to take a whole block
+        if self.max_block_size < self.block_size {
+            self.max_block_size = self.block_size;
+            self.host_in = vec![T::zero(); self.max_block_size * self.routing.inputs()];
+            self.host_out = vec![T::zero(); self.max_block_size * self.routing.outputs()];
+        }
+        self.head_out = vec![T::zero(); self.max_block_size * paths];
+
+        self.head = Some(Fir::new(
+            head,
+            paths,
+            self.fade_len.unwrap_or(self.block_size),
+            self.fade_curve,
+        ));
+
+        Ok(())
+    }
+
+    /// crossfades the head over to new taps, over the fade length or one block,
+    /// which have to be the same length as the ones it has now
+    pub fn update_head(&mut self, head: &[T]) -> Result<(), ConvError> {
+        let expected = self
+            .head
+            .as_ref()
+            .map_or(0, |h| h.len() * self.routing.paths());
+        match &mut self.head {
+            Some(fir) if head.len() == expected => {
+                fir.update_taps(head);
+                Ok(())
+            }
+            _ => Err(ConvError::HeadLength {
+                expected,
+                actual: head.len(),
+            }),
+        }
+    }
+
+    /// the partition the `Conv` runs, shorter filters have to be processed
+    /// for the `partition::cover` of it to be used with `update_filter`
+    pub fn partition(&self) -> &[(usize, usize)] {
+        &self.partition
+    }
+
+    pub fn routing(&self) -> Routing {
+        self.routing
+    }
+
+    /// the longest block `process` can take
+    pub fn max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    /// the longest filter `update_filter` can take, in samples
+    pub fn capacity(&self) -> usize {
+        self.partition.iter().map(|p| p.0 * p.1).sum()
+    }
+
+    /// the delay `process`, `process_interleaved` and `process_in_place` add, which is
+    /// a block, unless there's a head to cover it, `process_block` never adds any
+    pub fn latency(&self) -> usize {
+        if self.head.is_some() {
+            0
+        } else {
+            self.block_size
         }
     }
 
-    pub fn update_filter(
+    /// how many samples after the next block a filter update made now would start
+    /// fading in, which is as soon as every segment can fade at the same point in the output
+    ///
+    /// the blocks a background segment has already been sent are worked on with the old
+    /// filter, so its output can only change from the first block sent after the update,
+    /// which comes out `offset - avail` cycles after it's sent. this depends on how
+    /// far through its block each segment is, so it changes from one block to the next
+    pub fn fade_delay(&self) -> usize {
+        self.non_rt_segments
+            .iter()
+            .zip(&self.add_tails[1..])
+            .map(|(segment, tail)| {
+                (segment.first_out(self.cycle_count) - self.cycle_count) * self.block_size + tail
+            })
+            .fold(self.add_tails[0], usize::max)
+    }
+
+    /// how many samples `process` keeps putting out after the input goes silent,
+    /// which is the latency plus the length of the whole filter, head and all,
+    /// and while a filter update is fading in, the old filter is heard until it's over
+    pub fn tail_len(&self) -> usize {
+        // the tail's filter has the head's length in it already, less the block
+        // of latency, so adding the block back on gives the whole filter
+        let head_len = self.head.as_ref().map_or(0, |h| h.len());
+        let fading = self
+            .fade_end
+            .saturating_sub(self.cycle_count * self.block_size);
+        let len = self.source_len.max(self.old_source_len.min(fading));
+        (self.block_size + len).max(head_len)
+    }
+
+    /// roughly how many bytes the `Conv` takes up, counting every segment's filters,
+    /// fdl and buffers, and the queues to and from the workers
+    pub fn memory_usage(&self) -> usize {
+        let buffers = self.input_buff.len()
+            + self.output_buff.len()
+            + self.host_in.len()
+            + self.host_out.len()
+            + self.fifo_in.len()
+            + self.fifo_out.len()
+            + self.head_out.len();
+
+        buffers * std::mem::size_of::<T>()
+            + self.rt_segment.memory_usage()
+            + self
+                .non_rt_segments
+                .iter()
+                .map(|s| s.memory_usage())
+                .sum::<usize>()
+            + self.head.as_ref().map_or(0, |h| h.memory_usage())
+    }
+
+    /// sets what background segments play when they miss a block, this is real time safe
+    pub fn set_miss_policy(&mut self, miss_policy: MissPolicy) {
+        self.miss_policy = miss_policy;
+    }
+
+    /// makes a background segment miss the next block it has due, as if its worker
+    /// hadn't got it done in time, so the miss policy and the deadline monitor can be
+    /// tried out without having to overload the cpu, segments are indexed the same way
+    /// as the partition, and segment 0 can't miss, so nothing happens for that one
+    ///
+    /// this is real time safe
+    pub fn force_miss(&mut self, segment: usize) {
+        if let Some(segment) = segment
+            .checked_sub(1)
+            .and_then(|s| self.non_rt_segments.get_mut(s))
+        {
+            segment.forced_misses += 1;
+        }
+    }
+
+    /// a handle on the missed block counts, that can be polled from any thread
+    pub fn deadline_monitor(&self) -> Arc<DeadlineMonitor> {
+        self.deadline_monitor.clone()
+    }
+
+    /// clears every buffer, so nothing from before the reset is heard after it,
+    /// for when the transport stops or the host asks for a reset, afterwards
+    /// the `Conv` runs exactly like a new one would
+    ///
+    /// the real time segment, the head and anything run on the audio thread are
+    /// cleared straight away, and the workers are told to clear their state when
+    /// they get the first block sent after the reset, which keeps them in sync with
+    /// us however far behind they are. this is real time safe
+    pub fn reset(&mut self) {
+        self.rt_segment.reset();
+        if let Some(head) = &mut self.head {
+            head.reset();
+        }
+
+        self.input_buff.fill(T::zero());
+        self.output_buff.fill(T::zero());
+        self.fifo_in.fill(T::zero());
+        self.fifo_out.fill(T::zero());
+        self.fifo_pos = 0;
+        self.cycle_count = 0;
+        self.fade_end = 0;
+
+        // the tags keep counting up, so any blocks from before the reset that are
+        // still on their way back are older than the next one we read, and get thrown away
+        for segment in &mut self.non_rt_segments {
+            segment.next_out = segment.next_in;
+            segment.held = false;
+            segment.hold.fill(T::zero());
+
+            while segment.rt_cons.discard().is_some() {}
+
+            match &mut segment.engine {
+                Engine::Worker { reset_at, .. } => {
+                    reset_at.store(segment.next_in + 1, Ordering::Release)
+                }
+                Engine::Distributed(distributed) => {
+                    distributed.job = None;
+                    distributed.upconv.reset();
+                    // an update that was waiting to fade in goes straight in instead
+                    if let Some((len, _)) = distributed.pending_filter.0.take() {
+                        distributed
+                            .upconv
+                            .load_filter(&distributed.pending_filter.1[0..len]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// every segment crossfades from its old filter to the new one at the same point
+    /// in the output, `fade_delay` samples after the next block, over the fade length
+    /// if one was set or one block if not, using the curve the `Conv` was created with
+    ///
+    /// the new filter can be shorter than the `Conv`'s capacity, processed for the
+    /// `partition::cover` of its partition, and the segments and blocks it doesn't
+    /// reach are emptied, which takes their multiply accumulates off the cpu
+    ///
+    /// if a worker hasn't picked up the last update yet, nothing is changed and this
+    /// returns `ConvError::FilterQueueFull`, and if the last update hasn't finished
+    /// fading in it returns `ConvError::FadePending`, either way it can be tried again later
+    pub fn update_filter(&mut self, new_filter: &ProcessedFilter<T>) -> Result<(), ConvError> {
+        check_filter(new_filter, &self.partition, &self.fft_ratios, self.routing)?;
+        if self.cycle_count * self.block_size < self.fade_end {
+            return Err(ConvError::FadePending);
+        }
+        // either every segment gets the new filter or none of them do
+        if self
+            .non_rt_segments
+            .iter()
+            .any(|s| !s.has_room_for_filter())
+        {
+            return Err(ConvError::FilterQueueFull);
+        }
+
+        let fade_delay = self.fade_delay();
+        self.rt_segment
+            .update_filter(new_filter.segment(0), fade_delay)?;
+        self.old_source_len = self.source_len;
+        self.source_len = new_filter.source_len();
+
+        // each segment is told where the fade starts in its own output,
+        // counted from the first block sent to it after this
+        let fade_start = self.cycle_count * self.block_size + fade_delay;
+        for (i, seg) in self.non_rt_segments.iter_mut().enumerate() {
+            let out = seg.first_out(self.cycle_count) * self.block_size;
+            seg.queue_filter(
+                segment_or_empty(new_filter, i + 1),
+                seg.next_in * seg.block_size + fade_start - out,
+            )?;
+        }
+        self.fade_end = fade_start + self.fade_len.unwrap_or(self.block_size);
+
+        Ok(())
+    }
+
+    /// takes blocks of any length up to the max block size, and buffers them
+    /// internally against the block size, so everything that comes out of here
+    /// is delayed by an extra `block_size` samples
+    ///
+    /// every channel block has to be the same length, and this panics if they're longer
+    /// than the max block size, since the output is lent out of the engine's own buffers,
+    /// `process_interleaved` and `process_in_place` take blocks of any length
+    pub fn process<'block>(
         &mut self,
-        // chunks are on the outside, then channels inside that, then block inside that
-        new_filter: &[Complex<f32>],
-    ) {
-        let mut filter_index = 0;
-        let first = &new_filter[0..(self.partition[0].0 + 1) * self.partition[0].1 * self.channels];
-        self.rt_segment.update_filter(first);
-        filter_index += (self.partition[0].0 + 1) * self.partition[0].1 * self.channels;
-
-        for seg in self.non_rt_segments.iter_mut() {
-            let filter_chunk = &new_filter[filter_index
-                ..filter_index + ((seg.partition.0 + 1) * seg.partition.1 * self.channels)];
-            match seg
-                .filter_prod
-                .write_chunk((seg.partition.0 + 1) * seg.partition.1 * self.channels)
+        channel_blocks: impl Iterator<Item = &'block [T]>,
+    ) -> impl Iterator<Item = &[T]> {
+        let mut len = 0;
+        for (host_channel, block) in self
+            .host_in
+            .chunks_exact_mut(self.max_block_size)
+            .zip(channel_blocks)
+        {
+            assert!(
+                block.len() <= self.max_block_size,
+                "the block is longer than the max block size"
+            );
+            len = block.len();
+            host_channel[0..len].copy_from_slice(block);
+        }
+
+        self.process_host(len);
+
+        self.host_out
+            .chunks_exact(self.max_block_size)
+            .map(move |o| &o[0..len])
+    }
+
+    /// `process` for interleaved frames, `input` has a sample for every input in each frame
+    /// and `output` gets a sample for every output, and it has to have room for every frame
+    ///
+    /// any number of frames can be given, more than the max block size are done
+    /// a max block at a time
+    pub fn process_interleaved(&mut self, input: &[T], output: &mut [T]) {
+        let (inputs, outputs) = (self.routing.inputs(), self.routing.outputs());
+        debug_assert!(output.len() / outputs >= input.len() / inputs);
+
+        let max_block_size = self.max_block_size;
+        for (input, output) in input
+            .chunks(max_block_size * inputs)
+            .zip(output.chunks_mut(max_block_size * outputs))
+        {
+            let len = input.len() / inputs;
+            for (i, host_channel) in self.host_in.chunks_exact_mut(max_block_size).enumerate() {
+                for (h, frame) in host_channel[0..len]
+                    .iter_mut()
+                    .zip(input.chunks_exact(inputs))
+                {
+                    *h = frame[i];
+                }
+            }
+
+            self.process_host(len);
+
+            for (o, host_channel) in self.host_out.chunks_exact(max_block_size).enumerate() {
+                for (frame, h) in output.chunks_exact_mut(outputs).zip(&host_channel[0..len]) {
+                    frame[o] = *h;
+                }
+            }
+        }
+    }
+
+    /// `process` that writes straight back into the buffers it reads from, the first
+    /// `inputs` buffers are read and then the first `outputs` are written, so there
+    /// have to be as many buffers as whichever of those is bigger
+    ///
+    /// the buffers can be any length, longer than the max block size
+    /// and they're done a max block at a time
+    pub fn process_in_place(&mut self, buffers: &mut [&mut [T]]) {
+        debug_assert!(buffers.len() >= self.routing.inputs().max(self.routing.outputs()));
+
+        let len = buffers.first().map_or(0, |b| b.len());
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(self.max_block_size);
+            for (host_channel, buffer) in self
+                .host_in
+                .chunks_exact_mut(self.max_block_size)
+                .zip(buffers.iter())
             {
-                Ok(mut w) => {
-                    let (s1, s2) = w.as_mut_slices();
+                host_channel[0..n].copy_from_slice(&buffer[done..done + n]);
+            }
 
-                    s1.copy_from_slice(&filter_chunk[0..s1.len()]);
-                    s2.copy_from_slice(&filter_chunk[s1.len()..s1.len() + s2.len()]);
+            self.process_host(n);
+
+            for (host_channel, buffer) in self
+                .host_out
+                .chunks_exact(self.max_block_size)
+                .zip(buffers.iter_mut())
+            {
+                buffer[done..done + n].copy_from_slice(&host_channel[0..n]);
+            }
+            done += n;
+        }
+    }
+
+    /// runs the first `len` samples of the host input buffer through the fifo and the head
+    fn process_host(&mut self, len: usize) {
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(self.block_size - self.fifo_pos);
+
+            for (fifo_in, host_in) in self
+                .fifo_in
+                .chunks_exact_mut(self.block_size)
+                .zip(self.host_in.chunks_exact(self.max_block_size))
+            {
+                fifo_in[self.fifo_pos..self.fifo_pos + n].copy_from_slice(&host_in[done..done + n]);
+            }
+            for (fifo_out, host_out) in self
+                .fifo_out
+                .chunks_exact(self.block_size)
+                .zip(self.host_out.chunks_exact_mut(self.max_block_size))
+            {
+                host_out[done..done + n]
+                    .copy_from_slice(&fifo_out[self.fifo_pos..self.fifo_pos + n]);
+            }
 
-                    w.commit_all();
+            self.fifo_pos += n;
+            done += n;
+
+            if self.fifo_pos == self.block_size {
+                // taking the fifo out of self just moves the pointer,
+                // so this doesn't allocate
+                let fifo_in = std::mem::take(&mut self.fifo_in);
+                self.process_segments(fifo_in.chunks_exact(self.block_size));
+                self.fifo_in = fifo_in;
+
+                for (fifo_out, out_channel) in self
+                    .fifo_out
+                    .chunks_exact_mut(self.block_size)
+                    .zip(self.output_buff.chunks_exact(self.buff_len * 2))
+                {
+                    fifo_out.copy_from_slice(&out_channel[0..self.block_size]);
                 }
-                Err(_) => todo!(),
+
+                self.fifo_pos = 0;
+            }
+        }
+
+        if let Some(head) = &mut self.head {
+            let (routing, host_in, max_block_size) =
+                (self.routing, &self.host_in, self.max_block_size);
+            for head_out in self.head_out.chunks_exact_mut(max_block_size) {
+                head_out[0..len].fill(T::zero());
             }
+            head.process(
+                (0..routing.paths()).map(|p| {
+                    let input = routing.path(p).0;
+                    &host_in[max_block_size * input..max_block_size * input + len]
+                }),
+                self.head_out
+                    .chunks_exact_mut(max_block_size)
+                    .map(|o| &mut o[0..len]),
+            );
 
-            filter_index += (seg.partition.0 + 1) * seg.partition.1 * self.channels;
+            for (p, head_out) in self.head_out.chunks_exact(max_block_size).enumerate() {
+                let output = routing.path(p).1;
+                for (o, h) in self.host_out[max_block_size * output..max_block_size * output + len]
+                    .iter_mut()
+                    .zip(&head_out[0..len])
+                {
+                    *o += *h;
+                }
+            }
         }
     }
 
+    /// every channel block has to be exactly `block_size` long,
+    /// use `process` if the host can't promise that
+    ///
+    /// if there's a head this goes through `process`, so the latency is the same either way
     pub fn process_block<'block>(
         &mut self,
-        channel_blocks: impl Iterator<Item = &'block [f32]>,
-    ) -> impl Iterator<Item = &[f32]> {
+        channel_blocks: impl Iterator<Item = &'block [T]>,
+    ) -> impl Iterator<Item = &[T]> {
+        let (out, stride) = if self.head.is_some() {
+            // the output ends up in the host buffer
+            let _ = self.process(channel_blocks);
+            (&self.host_out, self.max_block_size)
+        } else {
+            self.process_segments(channel_blocks);
+            (&self.output_buff, self.buff_len * 2)
+        };
+
+        out.chunks_exact(stride).map(|o| &o[0..self.block_size])
+    }
+
+    fn process_segments<'block>(&mut self, channel_blocks: impl Iterator<Item = &'block [T]>) {
         // TODO reset this after big blocks, otherwise were gonna run out of space for usize
         self.cycle_count += 1;
 
-        for ((in_channel, out_channel), block) in self
+        for (in_channel, block) in self
             .input_buff
             .chunks_exact_mut(self.buff_len)
-            .zip(self.output_buff.chunks_exact_mut(self.buff_len * 2))
             .zip(channel_blocks)
         {
             in_channel.copy_within(self.block_size..self.buff_len, 0);
             in_channel[self.buff_len - self.block_size..self.buff_len].copy_from_slice(block);
+        }
 
+        for out_channel in self.output_buff.chunks_exact_mut(self.buff_len * 2) {
             out_channel.copy_within(self.block_size..self.buff_len * 2, 0);
-            out_channel[self.buff_len - self.block_size..self.buff_len].fill(0.0);
+            out_channel[self.buff_len * 2 - self.block_size..self.buff_len * 2].fill(T::zero());
         }
 
-        for segment in &mut self.non_rt_segments {
+        for (i, segment) in self.non_rt_segments.iter_mut().enumerate() {
             // first we check if its time to send and recieve a new block
-            if self.cycle_count % segment.avail == 0 {
-                match segment
-                    .rt_prod
-                    .write_chunk(segment.block_size * self.channels)
-                {
-                    Ok(mut w) => {
-                        let (s1, s2) = w.as_mut_slices();
-
-                        let mut s1_idx = 0;
-                        let mut s2_idx = 0;
-                        let s1_len = s1.len();
-
-                        for in_channel in self.input_buff.chunks_exact(self.buff_len) {
-                            let to_write =
-                                &in_channel[self.buff_len - segment.block_size..self.buff_len];
-                            if s1_idx + segment.block_size < s1.len() {
-                                s1[s1_idx..segment.block_size].copy_from_slice(to_write);
-
-                                s1_idx += segment.block_size;
-                            } else if s1_idx < s1.len() {
-                                s1[s1_idx..s1_len].copy_from_slice(&to_write[0..s1_len - s1_idx]);
-                                s2[0..segment.block_size - (s1.len() - s1_idx)].copy_from_slice(
-                                    &to_write[s1.len() - s1_idx..segment.block_size],
-                                );
-
-                                s2_idx += segment.block_size - (s1.len() - s1_idx);
-                                s1_idx = s1.len();
-                            } else {
-                                s2[s2_idx..segment.block_size].copy_from_slice(to_write);
-
-                                s2_idx += segment.block_size;
-                            }
-                        }
+            if self.cycle_count.is_multiple_of(segment.avail) {
+                let tag = segment.next_in;
+                segment.next_in += 1;
 
-                        w.commit_all();
-                    }
-                    Err(_) => todo!(),
-                }
+                let segment_block_size = segment.block_size;
+                segment.send(
+                    tag,
+                    self.input_buff
+                        .chunks_exact(self.buff_len)
+                        .map(|in_channel| {
+                            &in_channel[self.buff_len - segment_block_size..self.buff_len]
+                        }),
+                );
             }
 
+            segment.run_due();
+
             if self.cycle_count >= segment.offset
-                && (self.cycle_count - segment.offset) % segment.avail == 0
+                && (self.cycle_count - segment.offset).is_multiple_of(segment.avail)
             {
-                match segment
-                    .rt_cons
-                    .read_chunk(segment.block_size * self.channels)
-                {
-                    Ok(r) => {
-                        let (s1, s2) = r.as_slices();
-
-                        let mut s1_idx = 0;
-                        let mut s2_idx = 0;
-
-                        for out_channel in self.output_buff.chunks_exact_mut(self.buff_len * 2) {
-                            let to_write = &mut out_channel
-                                [self.block_size..segment.block_size + self.block_size];
-
-                            if s1_idx + segment.block_size < s1.len() {
-                                for (o, s) in to_write
-                                    .iter_mut()
-                                    .zip(&s1[s1_idx..s1_idx + segment.block_size])
-                                {
-                                    *o += s / (segment.block_size / self.block_size) as f32;
-                                }
-
-                                s1_idx += segment.block_size;
-                            } else if s1_idx < s1.len() {
-                                for (o, s) in to_write[0..s1.len() - s1_idx]
-                                    .iter_mut()
-                                    .zip(&s1[s1_idx..s1.len()])
-                                {
-                                    *o += s / (segment.block_size / self.block_size) as f32;
-                                }
-
-                                for (o, s) in to_write[s1.len() - s1_idx..segment.block_size]
-                                    .iter_mut()
-                                    .zip(&s2[s2_idx..segment.block_size - (s1.len() - s1_idx)])
-                                {
-                                    *o += s / (segment.block_size / self.block_size) as f32;
-                                }
-
-                                s2_idx += segment.block_size - (s1.len() - s1_idx);
-                                s1_idx = s1.len();
-                            } else {
-                                for (o, s) in to_write
-                                    .iter_mut()
-                                    .zip(&s2[s2_idx..s2_idx + segment.block_size])
-                                {
-                                    *o += s / (segment.block_size / self.block_size) as f32;
-                                }
-
-                                s2_idx += segment.block_size;
-                            }
+                let tag = segment.next_out;
+                segment.next_out += 1;
+
+                // anything older than the block we need now came back too late to be used
+                while segment.rt_cons.peek_tag().is_some_and(|t| t < tag) {
+                    segment.rt_cons.discard();
+                }
+
+                // a forced miss throws away the block, if it did make it back in time
+                if segment.forced_misses > 0 {
+                    segment.forced_misses -= 1;
+                    if segment.rt_cons.peek_tag() == Some(tag) {
+                        segment.rt_cons.discard();
+                    }
+                }
+
+                let fade = if segment.rt_cons.peek_tag() == Some(tag) {
+                    segment
+                        .rt_cons
+                        .pop_into(segment.hold.chunks_exact_mut(segment.block_size), |h, s| {
+                            *h = s
+                        });
+                    segment.held = true;
+
+                    false
+                } else {
+                    // the worker hasn't got the block done in time
+                    self.deadline_monitor.missed[i + 1].fetch_add(1, Ordering::Relaxed);
+
+                    match self.miss_policy {
+                        MissPolicy::Silence => {
+                            segment.held = false;
+                            false
                         }
+                        MissPolicy::Hold => false,
+                        MissPolicy::Fade => true,
+                    }
+                };
 
-                        r.commit_all();
+                if segment.held {
+                    for (out_channel, hold_channel) in self
+                        .output_buff
+                        .chunks_exact_mut(self.buff_len * 2)
+                        .zip(segment.hold.chunks_exact(segment.block_size))
+                    {
+                        for (j, (o, h)) in out_channel
+                            [self.block_size..segment.block_size + self.block_size]
+                            .iter_mut()
+                            .zip(hold_channel)
+                            .enumerate()
+                        {
+                            let gain = if fade {
+                                self.fade_curve
+                                    .gains(
+                                        T::from_usize(j).unwrap()
+                                            / T::from_usize(segment.block_size).unwrap(),
+                                    )
+                                    .0
+                            } else {
+                                T::one()
+                            };
+                            *o += *h * gain;
+                        }
                     }
-                    Err(_) => todo!(),
+
+                    // a faded out block has nothing left to hold
+                    segment.held = !fade;
                 }
             }
         }
@@ -315,9 +1437,152 @@ impl Conv {
                 *o += *n;
             }
         }
+    }
+}
+
+impl<T: Sample> Drop for Conv<T> {
+    /// stops every worker and waits for it to finish,
+    /// so this should happen off the audio thread
+    fn drop(&mut self) {
+        for segment in &mut self.non_rt_segments {
+            if let Engine::Worker {
+                worker, running, ..
+            } = &mut segment.engine
+            {
+                running.store(false, Ordering::Release);
+                if let Some(worker) = worker.take() {
+                    worker.thread().unpark();
+                    // a worker that panicked is already gone, there's nothing else to clean up
+                    let _ = worker.join();
+                }
+            }
+        }
+    }
+}
+
+/// starts a thread that runs `upconv` whenever the audio thread sends it a block,
+/// with room for `queue_blocks` blocks each way, past that new ones get dropped
+fn spawn_worker<T: Sample>(
+    mut upconv: UPConv<T>,
+    block_size: usize,
+    routing: Routing,
+    filter_len: usize,
+    queue_blocks: usize,
+) -> (Engine<T>, BlockConsumer<T>) {
+    let (rt_prod, mut seg_cons) = block_queue::<T, _>(block_size * routing.inputs(), queue_blocks);
+    let (mut seg_prod, rt_cons) = block_queue::<T, _>(block_size * routing.outputs(), queue_blocks);
+    let (filter_prod, mut filter_cons) = block_queue::<Complex<T>, _>(filter_len, 2);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let worker_running = running.clone();
+    let reset_at = Arc::new(AtomicUsize::new(0));
+    let worker_reset_at = reset_at.clone();
+
+    let worker = thread::spawn(move || {
+        let mut input = vec![T::zero(); block_size * routing.inputs()];
+        let mut filter = vec![
+            Complex {
+                re: T::zero(),
+                im: T::zero()
+            };
+            filter_len
+        ];
+        let mut reset_done = 0;
+        // an update waits here until the last one has finished fading in, with where its
+        // fade starts, and that's counted from the tag of the next block to be processed
+        let mut pending: Option<(usize, usize)> = None;
+        let mut next_tag = 0;
+
+        // the worker sleeps until the audio thread hands it something to do,
+        // and checks if it should shut down every time it wakes up
+        while worker_running.load(Ordering::Acquire) {
+            let mut idle = true;
+
+            if pending.is_none() {
+                pending = filter_cons.pop_into(std::iter::once(&mut filter[..]), |f, s| *f = s);
+                idle &= pending.is_none();
+            }
+            if let Some((len, fade_start)) = pending {
+                if upconv
+                    .update_filter(
+                        &filter[0..len],
+                        fade_start.saturating_sub(next_tag * block_size),
+                    )
+                    .is_ok()
+                {
+                    pending = None;
+                }
+            }
+
+            if let Some(tag) = seg_cons.pop_into(input.chunks_exact_mut(block_size), |i, s| *i = s)
+            {
+                idle = false;
+
+                // the reset lands between the last block from before it and the first from
+                // after it, however far behind the worker is
+                let reset = worker_reset_at.load(Ordering::Acquire);
+                if reset > reset_done && tag + 1 >= reset {
+                    upconv.reset();
+                    // an update that was waiting to fade in goes straight in instead
+                    if let Some((len, _)) = pending.take() {
+                        upconv.load_filter(&filter[0..len]);
+                    }
+                    reset_done = reset;
+                }
+
+                let out = upconv.process_block(input.chunks_exact(block_size));
+                next_tag = tag + 1;
+
+                // if the audio thread has stopped taking blocks out there's nobody
+                // to give this one to, it'll find out it's missing when it looks for it
+                let _ = seg_prod.push(tag, std::iter::once(out));
+            }
+
+            if idle {
+                thread::park();
+            }
+        }
+    });
+
+    let engine = Engine::Worker {
+        rt_prod,
+        filter_prod,
+        worker: Some(worker),
+        running,
+        reset_at,
+    };
+
+    (engine, rt_cons)
+}
+
+/// checks a filter was processed for `partition`, or the start of it,
+/// with a channel for every path of `routing`
+fn check_filter<T: Sample>(
+    filter: &ProcessedFilter<T>,
+    partition: &[(usize, usize)],
+    fft_ratios: &[usize],
+    routing: Routing,
+) -> Result<(), ConvError> {
+    if !partition::is_prefix(filter.partition(), partition)
+        || !fft_ratios.starts_with(filter.fft_ratios())
+    {
+        return Err(ConvError::PartitionMismatch);
+    }
+    if filter.channels() != routing.paths() {
+        return Err(ConvError::FilterCount {
+            expected: routing.paths(),
+            actual: filter.channels(),
+        });
+    }
+
+    Ok(())
+}
 
-        self.output_buff
-            .chunks_exact(self.buff_len * 2)
-            .map(|o| &o[0..self.block_size])
+/// the filter for a segment, which is empty if the filter doesn't reach it
+fn segment_or_empty<T: Sample>(filter: &ProcessedFilter<T>, segment: usize) -> &[Complex<T>] {
+    if segment < filter.partition().len() {
+        filter.segment(segment)
+    } else {
+        &[]
     }
 }
@@ -0,0 +1,366 @@
+use realfft::num_complex::Complex;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{conv::ConvError, sample::Sample};
+
+/*
+the file format is all little endian:
+- the magic bytes `CVPF` and a u32 version
+- a u64 checksum (fnv-1a) of everything after it
+- a u8 precision, which is the size of a sample in bytes, so 4 or 8
+- a u32 sample rate, which is 0 if it isn't known
+- a u32 channel count and a u64 source length
+- a u32 segment count, then a u64 block size, u64 block count and u64 fft ratio
+  for each segment
+- then the spectra, laid out the same way as in memory, as (re, im) pairs
+*/
+
+const MAGIC: &[u8; 4] = b"CVPF";
+const VERSION: u32 = 1;
+
+/// a filter that's been cut up and transformed for a partition by `process_filter`
+///
+/// the spectra are segment wise on the outside, then channel wise, then block wise,
+/// with `fft size / 2 + 1` bins per block, where a segment's fft size is its block
+/// size times its fft ratio, which is 2 unless the filter was padded
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessedFilter<T: Sample = f32> {
+    partition: Vec<(usize, usize)>,
+    fft_ratios: Vec<usize>,
+    channels: usize,
+    source_len: usize,
+    sample_rate: Option<u32>,
+    data: Vec<Complex<T>>,
+}
+
+#[derive(Debug)]
+pub enum FilterFileError {
+    Io(io::Error),
+    /// the file doesn't start with the magic bytes, so it isn't a filter file
+    NotAFilter,
+    UnsupportedVersion(u32),
+    /// the precision byte isn't the size of an f32 or an f64
+    UnsupportedPrecision(u8),
+    /// the file is corrupt, or has been cut short
+    ChecksumMismatch,
+    /// the header describes a layout that doesn't add up
+    InvalidLayout,
+}
+
+impl fmt::Display for FilterFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "couldn't read or write the filter: {e}"),
+            Self::NotAFilter => write!(f, "not a filter file"),
+            Self::UnsupportedVersion(v) => write!(f, "filter file version {v} isn't supported"),
+            Self::UnsupportedPrecision(p) => {
+                write!(f, "filter file has {p} byte samples, which isn't supported")
+            }
+            Self::ChecksumMismatch => write!(f, "filter file is corrupt"),
+            Self::InvalidLayout => write!(f, "filter file has an invalid layout"),
+        }
+    }
+}
+
+impl std::error::Error for FilterFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FilterFileError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl<T: Sample> ProcessedFilter<T> {
+    /// wraps spectra that are already laid out for `partition`,
+    /// `source_len` is how long the longest channel was before it was processed
+    pub fn from_raw(
+        partition: &[(usize, usize)],
+        channels: usize,
+        source_len: usize,
+        data: Vec<Complex<T>>,
+    ) -> Result<Self, ConvError> {
+        Self::from_raw_padded(
+            partition,
+            &vec![2; partition.len()],
+            channels,
+            source_len,
+            data,
+        )
+    }
+
+    /// `from_raw` for spectra with bigger ffts, `fft_ratios` has each
+    /// segment's fft size as a multiple of its block size
+    ///
+    /// `source_len` can't be more than the partition covers, the taps past it would be lost
+    pub fn from_raw_padded(
+        partition: &[(usize, usize)],
+        fft_ratios: &[usize],
+        channels: usize,
+        source_len: usize,
+        data: Vec<Complex<T>>,
+    ) -> Result<Self, ConvError> {
+        check_fft_ratios(fft_ratios, partition.len())?;
+
+        let capacity = partition.iter().map(|p| p.0 * p.1).sum();
+        if source_len > capacity {
+            return Err(ConvError::FilterTooLong {
+                len: source_len,
+                capacity,
+            });
+        }
+
+        let expected = partition
+            .iter()
+            .zip(fft_ratios)
+            .map(|(p, ratio)| (p.0 * ratio / 2 + 1) * p.1)
+            .sum::<usize>()
+            * channels;
+        if data.len() != expected {
+            return Err(ConvError::FilterLength {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        Ok(Self {
+            partition: Vec::from(partition),
+            fft_ratios: Vec::from(fft_ratios),
+            channels,
+            source_len,
+            sample_rate: None,
+            data,
+        })
+    }
+
+    /// records the sample rate the filter was made for, which is kept when it's saved
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    pub fn partition(&self) -> &[(usize, usize)] {
+        &self.partition
+    }
+
+    /// each segment's fft size, as a multiple of its block size
+    pub fn fft_ratios(&self) -> &[usize] {
+        &self.fft_ratios
+    }
+
+    /// which for a `Conv` is the number of paths it has
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// the length of the longest channel of the time domain filter
+    pub fn source_len(&self) -> usize {
+        self.source_len
+    }
+
+    pub fn data(&self) -> &[Complex<T>] {
+        &self.data
+    }
+
+    /// for filling in a filter in place, the layout can't change
+    pub fn data_mut(&mut self) -> &mut [Complex<T>] {
+        &mut self.data
+    }
+
+    /// every channel of one segment, one after the other
+    pub fn segment(&self, segment: usize) -> &[Complex<T>] {
+        let start = self.segment_start(segment);
+        &self.data[start..start + self.segment_len(segment)]
+    }
+
+    /// the blocks of one channel of one segment
+    pub fn channel(&self, segment: usize, channel: usize) -> &[Complex<T>] {
+        let channel_len = self.segment_len(segment) / self.channels;
+        let start = self.segment_start(segment) + channel_len * channel;
+        &self.data[start..start + channel_len]
+    }
+
+    /// writes the filter out in the filter file format, at the precision it's in
+    ///
+    /// this function is not real time safe
+    pub fn save(&self, mut writer: impl Write) -> Result<(), FilterFileError> {
+        let precision = std::mem::size_of::<T>() as u8;
+
+        let mut body = vec![precision];
+        body.extend_from_slice(&self.sample_rate.unwrap_or(0).to_le_bytes());
+        body.extend_from_slice(&(self.channels as u32).to_le_bytes());
+        body.extend_from_slice(&(self.source_len as u64).to_le_bytes());
+        body.extend_from_slice(&(self.partition.len() as u32).to_le_bytes());
+        for ((block_size, num_blocks), fft_ratio) in self.partition.iter().zip(&self.fft_ratios) {
+            body.extend_from_slice(&(*block_size as u64).to_le_bytes());
+            body.extend_from_slice(&(*num_blocks as u64).to_le_bytes());
+            body.extend_from_slice(&(*fft_ratio as u64).to_le_bytes());
+        }
+
+        body.reserve(self.data.len() * 2 * precision as usize);
+        for x in self.data.iter().flat_map(|c| [c.re, c.im]) {
+            match precision {
+                4 => body.extend_from_slice(&x.to_f32().unwrap().to_le_bytes()),
+                _ => body.extend_from_slice(&x.to_f64().unwrap().to_le_bytes()),
+            }
+        }
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&checksum(&body).to_le_bytes())?;
+        writer.write_all(&body)?;
+
+        Ok(())
+    }
+
+    /// reads a filter written by `save`, which gets converted to `T`
+    /// if it was saved at a different precision
+    ///
+    /// this function is not real time safe
+    pub fn load(mut reader: impl Read) -> Result<Self, FilterFileError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        let mut file = FileReader { bytes: &bytes };
+        if file.take(4)? != MAGIC {
+            return Err(FilterFileError::NotAFilter);
+        }
+        let version = file.u32()?;
+        if version != VERSION {
+            return Err(FilterFileError::UnsupportedVersion(version));
+        }
+        if file.u64()? != checksum(file.bytes) {
+            return Err(FilterFileError::ChecksumMismatch);
+        }
+
+        let precision = file.take(1)?[0];
+        if precision != 4 && precision != 8 {
+            return Err(FilterFileError::UnsupportedPrecision(precision));
+        }
+        let sample_rate = file.u32()?;
+        let channels = file.u32()? as usize;
+        let source_len = file.u64()? as usize;
+        let segments = file.u32()? as usize;
+
+        let mut partition = vec![];
+        let mut fft_ratios = vec![];
+        for _ in 0..segments {
+            partition.push((file.u64()? as usize, file.u64()? as usize));
+            fft_ratios.push(file.u64()? as usize);
+        }
+
+        // checked, so a bad header can't make us allocate the world
+        let bins = partition
+            .iter()
+            .zip(&fft_ratios)
+            .try_fold(0usize, |sum, ((block_size, num_blocks), fft_ratio)| {
+                (block_size.checked_mul(*fft_ratio)? / 2)
+                    .checked_add(1)?
+                    .checked_mul(*num_blocks)?
+                    .checked_mul(channels)?
+                    .checked_add(sum)
+            })
+            .ok_or(FilterFileError::InvalidLayout)?;
+        if Some(file.bytes.len()) != bins.checked_mul(2 * precision as usize) {
+            return Err(FilterFileError::InvalidLayout);
+        }
+
+        let data = file
+            .bytes
+            .chunks_exact(2 * precision as usize)
+            .map(|c| {
+                let (re, im) = c.split_at(precision as usize);
+                Complex {
+                    re: from_le_bytes(re),
+                    im: from_le_bytes(im),
+                }
+            })
+            .collect();
+
+        let filter = Self::from_raw_padded(&partition, &fft_ratios, channels, source_len, data)
+            .map_err(|_| FilterFileError::InvalidLayout)?;
+
+        Ok(match sample_rate {
+            0 => filter,
+            rate => filter.with_sample_rate(rate),
+        })
+    }
+
+    fn segment_len(&self, segment: usize) -> usize {
+        let (block_size, num_blocks) = self.partition[segment];
+        (block_size * self.fft_ratios[segment] / 2 + 1) * num_blocks * self.channels
+    }
+
+    fn segment_start(&self, segment: usize) -> usize {
+        (0..segment).map(|s| self.segment_len(s)).sum()
+    }
+}
+
+/// there has to be an fft ratio for every segment, and each one has to be at least 2
+pub(crate) fn check_fft_ratios(fft_ratios: &[usize], segments: usize) -> Result<(), ConvError> {
+    if fft_ratios.len() != segments {
+        return Err(ConvError::SchemeCount {
+            expected: segments,
+            actual: fft_ratios.len(),
+        });
+    }
+    match fft_ratios.iter().position(|r| *r < 2) {
+        Some(segment) => Err(ConvError::InvalidFftRatio {
+            segment,
+            ratio: fft_ratios[segment],
+        }),
+        None => Ok(()),
+    }
+}
+
+/// pulls little endian values off the front of a file
+struct FileReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> FileReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], FilterFileError> {
+        if self.bytes.len() < n {
+            return Err(FilterFileError::InvalidLayout);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, FilterFileError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, FilterFileError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// a sample of either precision, the length of `bytes` says which
+fn from_le_bytes<T: Sample>(bytes: &[u8]) -> T {
+    match bytes.len() {
+        4 => T::from_f32(f32::from_le_bytes(bytes.try_into().unwrap())).unwrap(),
+        _ => T::from_f64(f64::from_le_bytes(bytes.try_into().unwrap())).unwrap(),
+    }
+}
+
+/// fnv-1a, which is plenty to catch a corrupt or truncated file
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,162 @@
+use crate::{sample::Sample, upconv::FadeCurve};
+
+/// a plain time domain fir filter, for the first few taps of a filter,
+/// where even one block of latency is too much
+pub struct Fir<T: Sample = f32> {
+    // the taps are stored back to front, so each output sample is a straight
+    // dot product with the history
+    taps: Vec<T>,
+    old_taps: (bool, Vec<T>),
+    // every sample is written twice, `len` apart, so the last `len` samples
+    // are always in one contiguous slice
+    history: Vec<T>,
+    pos: usize,
+    len: usize,
+    fade_pos: usize,
+    fade_len: usize,
+    fade_curve: FadeCurve,
+}
+
+impl<T: Sample> Fir<T> {
+    /// `taps` is one slice of taps per channel, one after the other,
+    /// and filter changes are faded in over `fade_len` samples
+    pub fn new(taps: &[T], channels: usize, fade_len: usize, fade_curve: FadeCurve) -> Self {
+        let len = taps.len() / channels;
+
+        let mut fir = Self {
+            taps: vec![T::zero(); len * channels],
+            old_taps: (false, vec![T::zero(); len * channels]),
+            history: vec![T::zero(); len * 2 * channels],
+            pos: 0,
+            len,
+            fade_pos: 0,
+            fade_len: fade_len.max(1),
+            fade_curve,
+        };
+        fir.set_taps(taps);
+
+        fir
+    }
+
+    /// the number of taps per channel
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// the bytes taken up by the taps and the history
+    pub fn memory_usage(&self) -> usize {
+        (self.taps.len() + self.old_taps.1.len() + self.history.len()) * std::mem::size_of::<T>()
+    }
+
+    /// `taps` has to be the same length as the taps the filter was made with
+    pub fn update_taps(&mut self, taps: &[T]) {
+        // if the last update hasn't finished fading in, we fade from
+        // what we were fading to, which is close enough
+        self.old_taps.1.copy_from_slice(&self.taps);
+        self.old_taps.0 = true;
+        self.fade_pos = 0;
+
+        self.set_taps(taps);
+    }
+
+    /// forgets the input so far and ends any fade, this is real time safe
+    pub fn reset(&mut self) {
+        self.history.fill(T::zero());
+        self.pos = 0;
+        self.old_taps.0 = false;
+        self.fade_pos = 0;
+    }
+
+    /// convolves each input channel with its taps and adds the result to the output channel
+    pub fn process<'i, 'o>(
+        &mut self,
+        inputs: impl Iterator<Item = &'i [T]>,
+        outputs: impl Iterator<Item = &'o mut [T]>,
+    ) {
+        if self.len == 0 {
+            return;
+        }
+
+        let mut processed = 0;
+        for (((input, output), taps), (old_taps, history)) in inputs
+            .zip(outputs)
+            .zip(self.taps.chunks_exact(self.len))
+            .zip(
+                self.old_taps
+                    .1
+                    .chunks_exact(self.len)
+                    .zip(self.history.chunks_exact_mut(self.len * 2)),
+            )
+        {
+            let mut pos = self.pos;
+            let mut fade_pos = self.fade_pos;
+
+            for (x, y) in input.iter().zip(output.iter_mut()) {
+                history[pos] = *x;
+                history[pos + self.len] = *x;
+
+                let window = &history[pos + 1..pos + 1 + self.len];
+                let new = dot(window, taps);
+
+                *y += if self.old_taps.0 && fade_pos < self.fade_len {
+                    let (old_gain, new_gain) = self.fade_curve.gains(
+                        T::from_usize(fade_pos).unwrap() / T::from_usize(self.fade_len).unwrap(),
+                    );
+                    fade_pos += 1;
+
+                    new * new_gain + dot(window, old_taps) * old_gain
+                } else {
+                    new
+                };
+
+                pos = (pos + 1) % self.len;
+            }
+
+            processed = input.len().min(output.len());
+        }
+
+        self.pos = (self.pos + processed) % self.len;
+        if self.old_taps.0 {
+            self.fade_pos += processed;
+            if self.fade_pos >= self.fade_len {
+                self.old_taps.0 = false;
+            }
+        }
+    }
+
+    fn set_taps(&mut self, taps: &[T]) {
+        for (reversed, channel) in self
+            .taps
+            .chunks_exact_mut(self.len.max(1))
+            .zip(taps.chunks_exact(self.len.max(1)))
+        {
+            reversed.copy_from_slice(channel);
+            reversed.reverse();
+        }
+    }
+}
+
+/// written with a few independent accumulators so it vectorizes
+fn dot<T: Sample>(a: &[T], b: &[T]) -> T {
+    let mut acc = [T::zero(); 8];
+
+    let a_chunks = a.chunks_exact(8);
+    let b_chunks = b.chunks_exact(8);
+    let rest: T = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .fold(T::zero(), |sum, (a, b)| sum + *a * *b);
+
+    for (a, b) in a_chunks.zip(b_chunks) {
+        for ((acc, a), b) in acc.iter_mut().zip(a).zip(b) {
+            *acc += *a * *b;
+        }
+    }
+
+    acc.iter().fold(rest, |sum, a| sum + *a)
+}
@@ -1,19 +1,39 @@
 use realfft::{num_complex::Complex, RealFftPlanner};
 
-// TODO get rid of interleaving, make this more general
+use crate::{
+    conv::ConvError,
+    filter::{check_fft_ratios, ProcessedFilter},
+    sample::Sample,
+};
 
 /// this function is not real time safe
-/// outermost vec is segment wise,
-/// middle vec is channel wize
-/// innermost vec is block wise
-pub fn process_filter(filter: Vec<Vec<f32>>, partition: &[(usize, usize)]) -> Vec<Complex<f32>> {
-    let mut planner = RealFftPlanner::<f32>::new();
-    let mut ffts = partition.iter().map(|p| planner.plan_fft_forward(p.0 * 2));
-
-    let out_len: usize = partition
+/// `filter` has one vec per channel, and the spectra come back
+/// laid out the way `ProcessedFilter` describes
+///
+/// a filter longer than the partition covers is `FilterTooLong`, rather than cut short
+pub fn process_filter<T: Sample>(
+    filter: Vec<Vec<T>>,
+    partition: &[(usize, usize)],
+) -> Result<ProcessedFilter<T>, ConvError> {
+    process_filter_padded(filter, partition, &vec![2; partition.len()])
+}
+
+/// `process_filter` with bigger ffts, `fft_ratios` has each segment's fft size
+/// as a multiple of its block size, which has to be at least 2
+///
+/// this function is not real time safe
+pub fn process_filter_padded<T: Sample>(
+    filter: Vec<Vec<T>>,
+    partition: &[(usize, usize)],
+    fft_ratios: &[usize],
+) -> Result<ProcessedFilter<T>, ConvError> {
+    check_fft_ratios(fft_ratios, partition.len())?;
+
+    let mut planner = RealFftPlanner::<T>::new();
+    let mut ffts = partition
         .iter()
-        .map(|p| (p.0 + 1) * p.1 * filter.len())
-        .sum();
+        .zip(fft_ratios)
+        .map(|(p, ratio)| planner.plan_fft_forward(p.0 * ratio));
 
     let mut out = vec![];
 
@@ -28,7 +48,7 @@ pub fn process_filter(filter: Vec<Vec<f32>>, partition: &[(usize, usize)]) -> Ve
 
             for chunk in filter_chunk.chunks(part.0) {
                 let mut fft_in = fft.make_input_vec();
-                fft_in.fill(0.0);
+                fft_in.fill(T::zero());
                 fft_in[0..chunk.len()].copy_from_slice(chunk);
 
                 let mut fft_out = fft.make_output_vec();
@@ -38,8 +58,11 @@ pub fn process_filter(filter: Vec<Vec<f32>>, partition: &[(usize, usize)]) -> Ve
                 channel_vec.extend(fft_out);
             }
             channel_vec.extend(vec![
-                Complex { re: 0.0, im: 0.0 };
-                ((part.0 + 1) * part.1) - channel_vec.len()
+                Complex {
+                    re: T::zero(),
+                    im: T::zero()
+                };
+                (fft.len() / 2 + 1) * part.1 - channel_vec.len()
             ]);
 
             part_vec.extend(channel_vec);
@@ -48,6 +71,59 @@ pub fn process_filter(filter: Vec<Vec<f32>>, partition: &[(usize, usize)]) -> Ve
         filter_index += part.0 * part.1;
         out.extend(part_vec);
     }
-    assert!(out.len() == out_len);
-    out
+    let source_len = filter.iter().map(|f| f.len()).max().unwrap_or(0);
+    ProcessedFilter::from_raw_padded(partition, fft_ratios, filter.len(), source_len, out)
+}
+
+/// `process_filter` for a filter with its channels interleaved,
+/// like it comes out of a multichannel wav file
+///
+/// this function is not real time safe
+pub fn process_filter_interleaved<T: Sample>(
+    filter: &[T],
+    channels: usize,
+    partition: &[(usize, usize)],
+) -> Result<ProcessedFilter<T>, ConvError> {
+    process_filter(deinterleave(filter, channels), partition)
+}
+
+/// splits interleaved frames into one vec per channel, any partial frame at the end is dropped
+pub fn deinterleave<T: Copy>(samples: &[T], channels: usize) -> Vec<Vec<T>> {
+    (0..channels)
+        .map(|c| samples.chunks_exact(channels).map(|f| f[c]).collect())
+        .collect()
+}
+
+/// splits each channel of a filter into a head of `head_len` taps, for a `Fir`,
+/// and a tail for `process_filter`, so they can be used with `Conv::set_head`
+///
+/// the tail is delayed by the `block_size` samples of latency `Conv::process`
+/// adds, so `head_len` has to be at least `block_size`, or you get `HeadTooShort`
+///
+/// the head comes back with every channel one after the other
+pub fn split_head<T: Sample>(
+    filter: Vec<Vec<T>>,
+    head_len: usize,
+    block_size: usize,
+) -> Result<(Vec<T>, Vec<Vec<T>>), ConvError> {
+    if head_len < block_size {
+        return Err(ConvError::HeadTooShort {
+            head_len,
+            block_size,
+        });
+    }
+
+    let mut head = vec![T::zero(); head_len * filter.len()];
+    let mut tail = vec![];
+
+    for (head_channel, channel_filter) in head.chunks_exact_mut(head_len).zip(filter) {
+        let split = head_len.min(channel_filter.len());
+        head_channel[0..split].copy_from_slice(&channel_filter[0..split]);
+
+        let mut tail_channel = vec![T::zero(); head_len - block_size];
+        tail_channel.extend_from_slice(&channel_filter[split..]);
+        tail.push(tail_channel);
+    }
+
+    Ok((head, tail))
 }
@@ -0,0 +1,375 @@
+use hound::{SampleFormat, WavReader};
+use rubato::{FftFixedIn, ResampleError, Resampler, ResamplerConstructionError};
+use std::{fmt, io::Read, path::Path};
+
+use crate::{
+    conv::ConvError,
+    filter::ProcessedFilter,
+    helpers::{deinterleave, process_filter},
+    sample::Sample,
+    upconv::{FadeCurve, Routing},
+};
+
+/// an impulse response as plain samples, with one vec per channel
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImpulseResponse<T: Sample = f32> {
+    pub channels: Vec<Vec<T>>,
+    pub sample_rate: u32,
+}
+
+#[derive(Debug)]
+pub enum IrError {
+    Wav(hound::Error),
+    /// only 8 to 32 bit ints and 32 bit floats can be loaded
+    UnsupportedFormat {
+        format: SampleFormat,
+        bits: u16,
+    },
+    /// the file has no channels or no samples
+    Empty,
+    /// there's no sensible way to spread the channels of the ir over the paths of the routing
+    ChannelMismatch {
+        ir: usize,
+        routing: Routing,
+    },
+    InvalidSampleRate(u32),
+    ResamplerConstruction(ResamplerConstructionError),
+    Resample(ResampleError),
+    /// the ir couldn't be processed for the partition
+    Filter(ConvError),
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wav(e) => write!(f, "couldn't read the wav file: {e}"),
+            Self::UnsupportedFormat { format, bits } => {
+                write!(f, "{bits} bit {format:?} samples aren't supported")
+            }
+            Self::Empty => write!(f, "impulse response is empty"),
+            Self::ChannelMismatch { ir, routing } => write!(
+                f,
+                "impulse response has {ir} channels, which can't be mapped onto {} paths",
+                routing.paths()
+            ),
+            Self::InvalidSampleRate(rate) => write!(f, "{rate} isn't a valid sample rate"),
+            Self::ResamplerConstruction(e) => write!(f, "couldn't make the resampler: {e}"),
+            Self::Resample(e) => write!(f, "couldn't resample the impulse response: {e}"),
+            Self::Filter(e) => write!(f, "couldn't process the impulse response: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for IrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Wav(e) => Some(e),
+            Self::ResamplerConstruction(e) => Some(e),
+            Self::Resample(e) => Some(e),
+            Self::Filter(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<hound::Error> for IrError {
+    fn from(e: hound::Error) -> Self {
+        Self::Wav(e)
+    }
+}
+
+impl From<ResamplerConstructionError> for IrError {
+    fn from(e: ResamplerConstructionError) -> Self {
+        Self::ResamplerConstruction(e)
+    }
+}
+
+impl From<ResampleError> for IrError {
+    fn from(e: ResampleError) -> Self {
+        Self::Resample(e)
+    }
+}
+
+impl From<ConvError> for IrError {
+    fn from(e: ConvError) -> Self {
+        Self::Filter(e)
+    }
+}
+
+/// reads a wav file from anything, like the bytes from `include_bytes!`
+///
+/// this function is not real time safe
+pub fn load_wav<T: Sample>(reader: impl Read) -> Result<ImpulseResponse<T>, IrError> {
+    from_wav(WavReader::new(reader)?)
+}
+
+/// this function is not real time safe
+pub fn load_wav_file<T: Sample>(path: impl AsRef<Path>) -> Result<ImpulseResponse<T>, IrError> {
+    from_wav(WavReader::open(path)?)
+}
+
+fn from_wav<T: Sample, R: Read>(reader: WavReader<R>) -> Result<ImpulseResponse<T>, IrError> {
+    let spec = reader.spec();
+
+    let samples: Vec<T> = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Int, bits @ 8..=32) => {
+            // ints are scaled so full scale is 1, whatever size they are
+            let scale = 1.0 / (1u64 << (bits - 1)) as f64;
+            reader
+                .into_samples::<i32>()
+                .map(|s| Ok(T::from_f64(s? as f64 * scale).unwrap()))
+                .collect::<Result<_, hound::Error>>()?
+        }
+        (SampleFormat::Float, 32) => reader
+            .into_samples::<f32>()
+            .map(|s| Ok(T::from_f32(s?).unwrap()))
+            .collect::<Result<_, hound::Error>>()?,
+        (format, bits) => return Err(IrError::UnsupportedFormat { format, bits }),
+    };
+
+    if spec.channels == 0 || samples.len() < spec.channels as usize {
+        return Err(IrError::Empty);
+    }
+
+    Ok(ImpulseResponse {
+        channels: deinterleave(&samples, spec.channels as usize),
+        sample_rate: spec.sample_rate,
+    })
+}
+
+impl<T: Sample> ImpulseResponse<T> {
+    /// the length of the longest channel
+    pub fn len(&self) -> usize {
+        self.channels.iter().map(|c| c.len()).max().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// gives the ir one channel per path of `routing`
+    /// - if it already has one per path it's left alone
+    /// - a mono ir goes on every path
+    /// - for a single path, every channel is mixed down
+    /// - for a matrix, an ir with a channel per output goes from each input to
+    ///   the output with the same index, and the other paths are silent
+    pub fn map_channels(mut self, routing: Routing) -> Result<Self, IrError> {
+        let (ir, paths) = (self.channels.len(), routing.paths());
+
+        self.channels = if ir == paths {
+            self.channels
+        } else if ir == 1 {
+            vec![self.channels.remove(0); paths]
+        } else if paths == 1 {
+            let mut mixed = vec![T::zero(); self.len()];
+            let gain = T::one() / T::from_usize(ir).unwrap();
+            for channel in &self.channels {
+                for (m, s) in mixed.iter_mut().zip(channel) {
+                    *m += *s * gain;
+                }
+            }
+            vec![mixed]
+        } else if matches!(routing, Routing::Matrix { outputs, .. } if outputs == ir) {
+            (0..paths)
+                .map(|p| match routing.path(p) {
+                    (input, output) if input == output => self.channels[output].clone(),
+                    _ => vec![],
+                })
+                .collect()
+        } else {
+            return Err(IrError::ChannelMismatch { ir, routing });
+        };
+
+        Ok(self)
+    }
+
+    /// resamples every channel to `sample_rate`, this is done at f64 whatever `T` is
+    ///
+    /// this function is not real time safe
+    pub fn resample(self, sample_rate: u32) -> Result<Self, IrError> {
+        if sample_rate == 0 {
+            return Err(IrError::InvalidSampleRate(sample_rate));
+        }
+        if self.sample_rate == 0 {
+            return Err(IrError::InvalidSampleRate(self.sample_rate));
+        }
+        if sample_rate == self.sample_rate || self.is_empty() {
+            return Ok(Self {
+                sample_rate,
+                ..self
+            });
+        }
+
+        let len = self.len();
+        let input: Vec<Vec<f64>> = self
+            .channels
+            .iter()
+            .map(|c| {
+                let mut padded: Vec<f64> = c.iter().map(|s| s.to_f64().unwrap()).collect();
+                padded.resize(len, 0.0);
+                padded
+            })
+            .collect();
+
+        let mut resampler = FftFixedIn::<f64>::new(
+            self.sample_rate as usize,
+            sample_rate as usize,
+            1024,
+            2,
+            input.len(),
+        )?;
+
+        let delay = resampler.output_delay();
+        let out_len = (len as u64 * sample_rate as u64).div_ceil(self.sample_rate as u64) as usize;
+        let mut output = vec![vec![]; input.len()];
+
+        let mut pos = 0;
+        while pos + resampler.input_frames_next() <= len {
+            let n = resampler.input_frames_next();
+            let chunk: Vec<&[f64]> = input.iter().map(|c| &c[pos..pos + n]).collect();
+            append(&mut output, resampler.process(&chunk, None)?);
+            pos += n;
+        }
+        let rest: Vec<&[f64]> = input.iter().map(|c| &c[pos..]).collect();
+        append(&mut output, resampler.process_partial(Some(&rest), None)?);
+
+        // the resampler holds on to the end of the ir until it's been flushed through
+        while output[0].len() < delay + out_len {
+            append(
+                &mut output,
+                resampler.process_partial::<&[f64]>(None, None)?,
+            );
+        }
+
+        Ok(Self {
+            channels: output
+                .into_iter()
+                .map(|o| {
+                    o[delay..delay + out_len]
+                        .iter()
+                        .map(|s| T::from_f64(*s).unwrap())
+                        .collect()
+                })
+                .collect(),
+            sample_rate,
+        })
+    }
+
+    /*
+    the rest of these are offline edits that can be chained before `process`,
+    every channel gets the same edit so they stay lined up with each other
+    */
+
+    /// cuts off the silence at the start, up to the first sample
+    /// louder than `threshold` in any channel
+    pub fn trim_start(mut self, threshold: T) -> Self {
+        let start = self
+            .channels
+            .iter()
+            .filter_map(|c| c.iter().position(|s| s.abs() > threshold))
+            .min()
+            .unwrap_or(0);
+
+        for channel in &mut self.channels {
+            channel.drain(0..start.min(channel.len()));
+        }
+
+        self
+    }
+
+    /// cuts off everything after the last sample louder than `threshold` in any channel
+    pub fn trim_end(mut self, threshold: T) -> Self {
+        let end = self
+            .channels
+            .iter()
+            .filter_map(|c| c.iter().rposition(|s| s.abs() > threshold))
+            .max()
+            .map_or(0, |e| e + 1);
+
+        for channel in &mut self.channels {
+            channel.truncate(end);
+        }
+
+        self
+    }
+
+    /// cuts every channel down to at most `len` samples, so the ir fits a partition
+    pub fn truncate(mut self, len: usize) -> Self {
+        for channel in &mut self.channels {
+            channel.truncate(len);
+        }
+
+        self
+    }
+
+    /// scales every channel by the same gain, so the average energy (sum of squares)
+    /// per channel is `energy`, which keeps irs at about the same loudness as each other
+    pub fn normalize(mut self, energy: T) -> Self {
+        let total = self
+            .channels
+            .iter()
+            .flatten()
+            .fold(T::zero(), |sum, s| sum + *s * *s);
+        let average = total / T::from_usize(self.channels.len().max(1)).unwrap();
+
+        // a silent ir can't be made any louder
+        if average > T::zero() {
+            let gain = (energy / average).sqrt();
+            for s in self.channels.iter_mut().flatten() {
+                *s *= gain;
+            }
+        }
+
+        self
+    }
+
+    /// fades out the last `len` samples, counted back from the end of the longest channel
+    pub fn fade_out(mut self, len: usize, curve: FadeCurve) -> Self {
+        let end = self.len();
+        let start = end.saturating_sub(len);
+        let len = T::from_usize(end - start).unwrap();
+
+        for channel in &mut self.channels {
+            for (i, s) in channel.iter_mut().enumerate().skip(start) {
+                let position = T::from_usize(i - start).unwrap() / len;
+                *s *= curve.gains(position).0;
+            }
+        }
+
+        self
+    }
+
+    /// delays every channel by `samples` of silence
+    pub fn predelay(mut self, samples: usize) -> Self {
+        for channel in &mut self.channels {
+            channel.splice(0..0, std::iter::repeat_n(T::zero(), samples));
+        }
+
+        self
+    }
+
+    /// plays the ir backwards, shorter channels are padded out to
+    /// the longest first, so they all end up starting together
+    pub fn reverse(mut self) -> Self {
+        let len = self.len();
+        for channel in &mut self.channels {
+            channel.resize(len, T::zero());
+            channel.reverse();
+        }
+
+        self
+    }
+
+    /// runs `process_filter` on the ir, keeping its sample rate
+    ///
+    /// this function is not real time safe
+    pub fn process(&self, partition: &[(usize, usize)]) -> Result<ProcessedFilter<T>, IrError> {
+        Ok(process_filter(self.channels.clone(), partition)?.with_sample_rate(self.sample_rate))
+    }
+}
+
+fn append(output: &mut [Vec<f64>], chunk: Vec<Vec<f64>>) {
+    for (o, c) in output.iter_mut().zip(chunk) {
+        o.extend(c);
+    }
+}
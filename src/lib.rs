@@ -1,3 +1,11 @@
 pub mod conv;
+pub mod filter;
+pub mod fir;
 pub mod helpers;
+pub mod ir;
+pub mod partition;
+mod queue;
+pub mod sample;
+pub mod simd;
+pub mod swap;
 pub mod upconv;
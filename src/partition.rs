@@ -0,0 +1,215 @@
+/*
+a partition is a list of (block size, number of blocks) pairs, one per segment,
+in the order they appear in the filter.
+
+for `Conv` to be able to schedule it, a partition has to look like this:
+- the first segment is processed in the audio callback, so its block size is the
+  block size `Conv` is run with
+- every other block size is a power of two multiple of the first one,
+  and block sizes never shrink from one segment to the next
+- a segment can't start in the filter before its first block of input has
+  been collected and processed, so its offset (the number of filter samples in
+  the segments before it) has to be bigger than its block size, and the extra is
+  how long its worker has to get the block done
+*/
+
+use crate::conv::ConvError;
+
+/// the knobs for `plan`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Constraints {
+    /// the biggest block size any segment is allowed to use
+    pub max_block_size: usize,
+    /// how many of its own block periods a segment gets to process a block,
+    /// so a segment with block size `b` starts at least `(headroom + 1) * b`
+    /// samples into the filter
+    pub headroom: usize,
+    /// cost of a real fft of size `n`, per `n * log2(n)`
+    pub fft_cost: f32,
+    /// cost of a single complex multiply accumulate
+    pub mac_cost: f32,
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        // the costs are rough flop counts
+        Self {
+            max_block_size: 16384,
+            headroom: 1,
+            fft_cost: 2.5,
+            mac_cost: 8.0,
+        }
+    }
+}
+
+/// finds the cheapest partition for a filter of `ir_len` samples according to the
+/// cost model in `constraints`, that `Conv` can run with `block_size` sized blocks
+///
+/// this function is not real time safe
+pub fn plan(
+    ir_len: usize,
+    block_size: usize,
+    channels: usize,
+    constraints: &Constraints,
+) -> Result<Vec<(usize, usize)>, ConvError> {
+    if block_size == 0 {
+        return Err(ConvError::ZeroBlockSize);
+    }
+    if constraints.headroom == 0 {
+        return Err(ConvError::NoHeadroom);
+    }
+
+    let mut sizes = vec![];
+    let mut size = block_size * 2;
+    while size <= constraints.max_block_size {
+        sizes.push(size);
+        size *= 2;
+    }
+
+    let mut best = vec![(block_size, ir_len.div_ceil(block_size).max(1))];
+    let mut best_cost = cost(&best, channels, constraints);
+
+    // every subset of the bigger block sizes gives a different sequence of segments,
+    // and there are only ever a handful of them, so we just try them all
+    for mask in 1..1usize << sizes.len() {
+        let chosen = sizes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| mask & (1 << i) != 0)
+            .map(|(_, s)| *s);
+
+        if let Some(partition) = build(ir_len, block_size, chosen, constraints) {
+            let c = cost(&partition, channels, constraints);
+            if c < best_cost {
+                best = partition;
+                best_cost = c;
+            }
+        }
+    }
+
+    Ok(best)
+}
+
+/// the cost per output sample of running `partition`, according to the cost model in `constraints`
+pub fn cost(partition: &[(usize, usize)], channels: usize, constraints: &Constraints) -> f32 {
+    partition
+        .iter()
+        .map(|(block_size, num_blocks)| {
+            let fft_len = (block_size * 2) as f32;
+            // one forward and one inverse fft, and a multiply accumulate
+            // for every bin of every block, all once per block
+            let ffts = 2.0 * constraints.fft_cost * fft_len * fft_len.log2();
+            let macs = constraints.mac_cost * ((block_size + 1) * num_blocks) as f32;
+
+            (ffts + macs) / *block_size as f32
+        })
+        .sum::<f32>()
+        * channels as f32
+}
+
+/// makes each segment just long enough for the next one to be allowed to start,
+/// and the last one long enough to cover the filter, or gives up if the filter
+/// ends before we get to use all the block sizes
+fn build(
+    ir_len: usize,
+    block_size: usize,
+    sizes: impl Iterator<Item = usize>,
+    constraints: &Constraints,
+) -> Option<Vec<(usize, usize)>> {
+    let mut partition = vec![];
+    let mut offset = 0;
+    let mut current = block_size;
+
+    for next in sizes {
+        if offset >= ir_len && !partition.is_empty() {
+            return None;
+        }
+
+        let start = next * (constraints.headroom + 1);
+        let num_blocks = start.saturating_sub(offset).div_ceil(current).max(1);
+
+        partition.push((current, num_blocks));
+        offset += current * num_blocks;
+        current = next;
+    }
+
+    if offset >= ir_len && !partition.is_empty() {
+        return None;
+    }
+
+    partition.push((current, (ir_len - offset).div_ceil(current).max(1)));
+
+    Some(partition)
+}
+
+/// checks that `Conv` can schedule `partition` when it's run with `block_size` sized blocks
+pub fn validate(partition: &[(usize, usize)], block_size: usize) -> Result<(), ConvError> {
+    if block_size == 0 {
+        return Err(ConvError::ZeroBlockSize);
+    }
+
+    let Some(first) = partition.first() else {
+        return Err(ConvError::EmptyPartition);
+    };
+    if first.0 != block_size {
+        return Err(ConvError::BlockSizeMismatch {
+            block_size,
+            first_segment: first.0,
+        });
+    }
+
+    let mut offset = 0;
+    let mut previous = block_size;
+    for (segment, (size, num_blocks)) in partition.iter().copied().enumerate() {
+        if num_blocks == 0 {
+            return Err(ConvError::EmptySegment { segment });
+        }
+        if size % block_size != 0 || !(size / block_size).is_power_of_two() || size < previous {
+            return Err(ConvError::InvalidSegmentSize { segment, size });
+        }
+        if segment > 0 && offset <= size {
+            return Err(ConvError::SegmentTooEarly {
+                segment,
+                offset,
+                size,
+            });
+        }
+
+        offset += size * num_blocks;
+        previous = size;
+    }
+
+    Ok(())
+}
+
+/// the first segments of `partition` that cover `len` samples, with the last one cut
+/// down to as few blocks as it needs, so a filter shorter than what a `Conv` was made for
+/// can be processed for just the part of the partition it fills
+pub fn cover(partition: &[(usize, usize)], len: usize) -> Vec<(usize, usize)> {
+    let mut covered = vec![];
+    let mut offset = 0;
+    for (size, num_blocks) in partition.iter().copied() {
+        // there's always at least one block, even for an empty filter
+        if offset >= len && !covered.is_empty() {
+            break;
+        }
+        covered.push((
+            size,
+            (len - offset.min(len)).div_ceil(size).clamp(1, num_blocks),
+        ));
+        offset += size * num_blocks;
+    }
+
+    covered
+}
+
+/// whether `prefix` is what `cover` gives for `partition` for some length
+pub(crate) fn is_prefix(prefix: &[(usize, usize)], partition: &[(usize, usize)]) -> bool {
+    match prefix.split_last() {
+        Some((last, rest)) if prefix.len() <= partition.len() => {
+            let full = partition[rest.len()];
+            partition.starts_with(rest) && last.0 == full.0 && last.1 <= full.1
+        }
+        _ => false,
+    }
+}
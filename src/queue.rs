@@ -0,0 +1,148 @@
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::mem::size_of;
+
+/// a ring buffer of fixed size blocks, where every block is tagged with the
+/// cycle it belongs to, so the reading side can tell when it's been handed
+/// a block that's too late, or missed one entirely
+pub(crate) fn block_queue<T: Copy + Default, G: Copy>(
+    block_len: usize,
+    capacity: usize,
+) -> (BlockProducer<T, G>, BlockConsumer<T, G>) {
+    let (samples_prod, samples_cons) = RingBuffer::<T>::new(block_len * capacity);
+    let (tags_prod, tags_cons) = RingBuffer::<G>::new(capacity);
+
+    (
+        BlockProducer {
+            samples: samples_prod,
+            tags: tags_prod,
+            block_len,
+        },
+        BlockConsumer {
+            samples: samples_cons,
+            tags: tags_cons,
+            block_len,
+        },
+    )
+}
+
+/// the queue had no room for another block
+#[derive(Debug)]
+pub(crate) struct QueueFull;
+
+// blocks are usually tagged with their cycle, but filter updates carry a bit more than that
+pub(crate) struct BlockProducer<T, G = usize> {
+    samples: Producer<T>,
+    tags: Producer<G>,
+    block_len: usize,
+}
+
+pub(crate) struct BlockConsumer<T, G = usize> {
+    samples: Consumer<T>,
+    tags: Consumer<G>,
+    block_len: usize,
+}
+
+impl<T: Copy + Default, G: Copy> BlockProducer<T, G> {
+    /// writes one block made of `parts` laid end to end,
+    /// if there isn't room for the whole block nothing gets written
+    pub fn push<'a>(
+        &mut self,
+        tag: G,
+        parts: impl Iterator<Item = &'a [T]>,
+    ) -> Result<(), QueueFull>
+    where
+        T: 'a,
+    {
+        if self.tags.is_full() {
+            return Err(QueueFull);
+        }
+
+        let mut w = self
+            .samples
+            .write_chunk(self.block_len)
+            .map_err(|_| QueueFull)?;
+        let (s1, s2) = w.as_mut_slices();
+
+        let mut idx = 0;
+        for part in parts {
+            let in_first = s1.len().saturating_sub(idx).min(part.len());
+            if in_first > 0 {
+                s1[idx..idx + in_first].copy_from_slice(&part[0..in_first]);
+            }
+
+            let rest = &part[in_first..];
+            let s2_idx = (idx + in_first).saturating_sub(s1.len());
+            s2[s2_idx..s2_idx + rest.len()].copy_from_slice(rest);
+
+            idx += part.len();
+        }
+
+        w.commit_all();
+
+        // the tag goes in after the samples, so once the reader can see it
+        // the samples are guaranteed to be there too
+        self.tags.push(tag).map_err(|_| QueueFull)
+    }
+
+    pub fn has_room(&self) -> bool {
+        !self.tags.is_full() && self.samples.slots() >= self.block_len
+    }
+
+    /// the bytes the whole queue takes up, both ends share it
+    pub fn memory_usage(&self) -> usize {
+        self.samples.buffer().capacity() * size_of::<T>()
+            + self.tags.buffer().capacity() * size_of::<G>()
+    }
+}
+
+impl<T: Copy, G: Copy> BlockConsumer<T, G> {
+    /// the tag of the oldest block in the queue, if there is one
+    pub fn peek_tag(&self) -> Option<G> {
+        self.tags.peek().ok().copied()
+    }
+
+    /// reads the oldest block into `parts`, laid end to end, using `op` to
+    /// combine each sample with what's already there, and returns its tag
+    pub fn pop_into<'a>(
+        &mut self,
+        parts: impl Iterator<Item = &'a mut [T]>,
+        mut op: impl FnMut(&mut T, T),
+    ) -> Option<G>
+    where
+        T: 'a,
+    {
+        let tag = self.tags.pop().ok()?;
+
+        // this can't fail, the samples always go in before the tag
+        let r = self.samples.read_chunk(self.block_len).ok()?;
+        let (s1, s2) = r.as_slices();
+
+        let mut idx = 0;
+        for part in parts {
+            for (o, s) in part.iter_mut().zip(s1.iter().chain(s2).skip(idx)) {
+                op(o, *s);
+            }
+            idx += part.len();
+        }
+
+        r.commit_all();
+
+        Some(tag)
+    }
+
+    /// the bytes the whole queue takes up, both ends share it
+    pub fn memory_usage(&self) -> usize {
+        self.samples.buffer().capacity() * size_of::<T>()
+            + self.tags.buffer().capacity() * size_of::<G>()
+    }
+
+    /// throws away the oldest block, and returns its tag
+    pub fn discard(&mut self) -> Option<G> {
+        let tag = self.tags.pop().ok()?;
+        if let Ok(r) = self.samples.read_chunk(self.block_len) {
+            r.commit_all();
+        }
+
+        Some(tag)
+    }
+}
@@ -0,0 +1,26 @@
+use realfft::{
+    num_complex::Complex,
+    num_traits::{Float, FloatConst, NumAssign},
+    FftNum,
+};
+
+use crate::simd::{self, Kernel};
+
+/// the sample types the engine can run at, so f32 and f64
+pub trait Sample: FftNum + Float + FloatConst + NumAssign + Default {
+    /// `accum += a * b` for every bin, with `kernel`, or with the
+    /// scalar loop if this cpu can't run it
+    fn mac(kernel: Kernel, accum: &mut [Complex<Self>], a: &[Complex<Self>], b: &[Complex<Self>]);
+}
+
+impl Sample for f32 {
+    fn mac(kernel: Kernel, accum: &mut [Complex<Self>], a: &[Complex<Self>], b: &[Complex<Self>]) {
+        simd::mac_f32(kernel, accum, a, b);
+    }
+}
+
+impl Sample for f64 {
+    fn mac(kernel: Kernel, accum: &mut [Complex<Self>], a: &[Complex<Self>], b: &[Complex<Self>]) {
+        simd::mac_f64(kernel, accum, a, b);
+    }
+}
@@ -0,0 +1,382 @@
+use realfft::num_complex::Complex;
+
+use crate::sample::Sample;
+
+/*
+the complex multiply accumulate, `accum += a * b` over every bin of a spectrum,
+is where nearly all the time goes for a long filter, so it has vectorized kernels
+that are picked at runtime. the spectra stay interleaved the way realfft wants them,
+and each kernel splits the real and imaginary parts apart in its registers, so
+nothing else has to know about it. every kernel does as many bins as fit in its
+registers and leaves whatever is left over for the scalar loop
+*/
+
+/// the instruction sets the multiply accumulate can be run with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kernel {
+    Scalar,
+    Sse2,
+    /// avx2 along with fma
+    Avx2,
+    Avx512,
+    Neon,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 5] = [
+        Self::Scalar,
+        Self::Sse2,
+        Self::Avx2,
+        Self::Avx512,
+        Self::Neon,
+    ];
+
+    /// the fastest kernel this cpu can run, which is checked at runtime
+    pub fn best() -> Self {
+        [Self::Avx512, Self::Avx2, Self::Sse2, Self::Neon]
+            .into_iter()
+            .find(|k| k.is_available())
+            .unwrap_or(Self::Scalar)
+    }
+
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::Scalar => true,
+            // every x86_64 cpu has sse2, and every aarch64 one has neon
+            #[cfg(target_arch = "x86_64")]
+            Self::Sse2 => true,
+            #[cfg(target_arch = "x86_64")]
+            Self::Avx2 => is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma"),
+            #[cfg(target_arch = "x86_64")]
+            Self::Avx512 => is_x86_feature_detected!("avx512f"),
+            #[cfg(target_arch = "aarch64")]
+            Self::Neon => true,
+            _ => false,
+        }
+    }
+}
+
+/// `accum += a * b` for every bin, one at a time, which the kernels are checked against
+pub fn scalar<T: Sample>(accum: &mut [Complex<T>], a: &[Complex<T>], b: &[Complex<T>]) {
+    for ((acc, a), b) in accum.iter_mut().zip(a).zip(b) {
+        *acc += a * b;
+    }
+}
+
+pub(crate) fn mac_f32(
+    kernel: Kernel,
+    accum: &mut [Complex<f32>],
+    a: &[Complex<f32>],
+    b: &[Complex<f32>],
+) {
+    let len = accum.len().min(a.len()).min(b.len());
+
+    // SAFETY: the kernel has been checked to run on this cpu, and only touches the first `len` bins
+    let done = match kernel {
+        _ if !kernel.is_available() => 0,
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Sse2 => unsafe { x86::f32_sse2(accum, a, b, len) },
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 => unsafe { x86::f32_avx2(accum, a, b, len) },
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx512 => unsafe { x86::f32_avx512(accum, a, b, len) },
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon => unsafe { neon::f32_neon(accum, a, b, len) },
+        _ => 0,
+    };
+
+    scalar(&mut accum[done..len], &a[done..len], &b[done..len]);
+}
+
+pub(crate) fn mac_f64(
+    kernel: Kernel,
+    accum: &mut [Complex<f64>],
+    a: &[Complex<f64>],
+    b: &[Complex<f64>],
+) {
+    let len = accum.len().min(a.len()).min(b.len());
+
+    // SAFETY: the kernel has been checked to run on this cpu, and only touches the first `len` bins
+    let done = match kernel {
+        _ if !kernel.is_available() => 0,
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Sse2 => unsafe { x86::f64_sse2(accum, a, b, len) },
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 => unsafe { x86::f64_avx2(accum, a, b, len) },
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx512 => unsafe { x86::f64_avx512(accum, a, b, len) },
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon => unsafe { neon::f64_neon(accum, a, b, len) },
+        _ => 0,
+    };
+
+    scalar(&mut accum[done..len], &a[done..len], &b[done..len]);
+}
+
+/*
+a complex is two floats side by side (it's repr(c)), so a register full of them
+is [re, im, re, im, ...]. to multiply them we make one copy of `a` with the real
+parts duplicated, one with the imaginary parts duplicated, and swap each pair in `b`:
+
+    a_re * b      = [a.re * b.re, a.re * b.im]
+    a_im * b_swap = [a.im * b.im, a.im * b.re]
+
+and then subtracting the second from the first in the real lanes and adding it
+in the imaginary ones gives the product
+*/
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use realfft::num_complex::Complex;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn f32_sse2(
+        accum: &mut [Complex<f32>],
+        a: &[Complex<f32>],
+        b: &[Complex<f32>],
+        len: usize,
+    ) -> usize {
+        let (acc, a, b) = (
+            accum.as_mut_ptr() as *mut f32,
+            a.as_ptr() as *const f32,
+            b.as_ptr() as *const f32,
+        );
+        // sse2 has no addsub, so the real lanes get negated with their sign bit
+        let sign = _mm_set_ps(0.0, -0.0, 0.0, -0.0);
+
+        let bins = len - len % 2;
+        for i in (0..bins * 2).step_by(4) {
+            let x = _mm_loadu_ps(a.add(i));
+            let y = _mm_loadu_ps(b.add(i));
+
+            let re = _mm_shuffle_ps::<0b10_10_00_00>(x, x);
+            let im = _mm_shuffle_ps::<0b11_11_01_01>(x, x);
+            let swapped = _mm_shuffle_ps::<0b10_11_00_01>(y, y);
+
+            let product = _mm_add_ps(_mm_mul_ps(re, y), _mm_xor_ps(_mm_mul_ps(im, swapped), sign));
+            _mm_storeu_ps(acc.add(i), _mm_add_ps(_mm_loadu_ps(acc.add(i)), product));
+        }
+
+        bins
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn f32_avx2(
+        accum: &mut [Complex<f32>],
+        a: &[Complex<f32>],
+        b: &[Complex<f32>],
+        len: usize,
+    ) -> usize {
+        let (acc, a, b) = (
+            accum.as_mut_ptr() as *mut f32,
+            a.as_ptr() as *const f32,
+            b.as_ptr() as *const f32,
+        );
+
+        let bins = len - len % 4;
+        for i in (0..bins * 2).step_by(8) {
+            let x = _mm256_loadu_ps(a.add(i));
+            let y = _mm256_loadu_ps(b.add(i));
+
+            let re = _mm256_moveldup_ps(x);
+            let im = _mm256_movehdup_ps(x);
+            let swapped = _mm256_permute_ps::<0b10_11_00_01>(y);
+
+            let product = _mm256_fmaddsub_ps(re, y, _mm256_mul_ps(im, swapped));
+            _mm256_storeu_ps(
+                acc.add(i),
+                _mm256_add_ps(_mm256_loadu_ps(acc.add(i)), product),
+            );
+        }
+
+        bins
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn f32_avx512(
+        accum: &mut [Complex<f32>],
+        a: &[Complex<f32>],
+        b: &[Complex<f32>],
+        len: usize,
+    ) -> usize {
+        let (acc, a, b) = (
+            accum.as_mut_ptr() as *mut f32,
+            a.as_ptr() as *const f32,
+            b.as_ptr() as *const f32,
+        );
+
+        let bins = len - len % 8;
+        for i in (0..bins * 2).step_by(16) {
+            let x = _mm512_loadu_ps(a.add(i));
+            let y = _mm512_loadu_ps(b.add(i));
+
+            let re = _mm512_moveldup_ps(x);
+            let im = _mm512_movehdup_ps(x);
+            let swapped = _mm512_permute_ps::<0b10_11_00_01>(y);
+
+            let product = _mm512_fmaddsub_ps(re, y, _mm512_mul_ps(im, swapped));
+            _mm512_storeu_ps(
+                acc.add(i),
+                _mm512_add_ps(_mm512_loadu_ps(acc.add(i)), product),
+            );
+        }
+
+        bins
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn f64_sse2(
+        accum: &mut [Complex<f64>],
+        a: &[Complex<f64>],
+        b: &[Complex<f64>],
+        len: usize,
+    ) -> usize {
+        let (acc, a, b) = (
+            accum.as_mut_ptr() as *mut f64,
+            a.as_ptr() as *const f64,
+            b.as_ptr() as *const f64,
+        );
+        let sign = _mm_set_pd(0.0, -0.0);
+
+        for i in (0..len * 2).step_by(2) {
+            let x = _mm_loadu_pd(a.add(i));
+            let y = _mm_loadu_pd(b.add(i));
+
+            let re = _mm_unpacklo_pd(x, x);
+            let im = _mm_unpackhi_pd(x, x);
+            let swapped = _mm_shuffle_pd::<0b01>(y, y);
+
+            let product = _mm_add_pd(_mm_mul_pd(re, y), _mm_xor_pd(_mm_mul_pd(im, swapped), sign));
+            _mm_storeu_pd(acc.add(i), _mm_add_pd(_mm_loadu_pd(acc.add(i)), product));
+        }
+
+        len
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn f64_avx2(
+        accum: &mut [Complex<f64>],
+        a: &[Complex<f64>],
+        b: &[Complex<f64>],
+        len: usize,
+    ) -> usize {
+        let (acc, a, b) = (
+            accum.as_mut_ptr() as *mut f64,
+            a.as_ptr() as *const f64,
+            b.as_ptr() as *const f64,
+        );
+
+        let bins = len - len % 2;
+        for i in (0..bins * 2).step_by(4) {
+            let x = _mm256_loadu_pd(a.add(i));
+            let y = _mm256_loadu_pd(b.add(i));
+
+            let re = _mm256_movedup_pd(x);
+            let im = _mm256_permute_pd::<0b1111>(x);
+            let swapped = _mm256_permute_pd::<0b0101>(y);
+
+            let product = _mm256_fmaddsub_pd(re, y, _mm256_mul_pd(im, swapped));
+            _mm256_storeu_pd(
+                acc.add(i),
+                _mm256_add_pd(_mm256_loadu_pd(acc.add(i)), product),
+            );
+        }
+
+        bins
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn f64_avx512(
+        accum: &mut [Complex<f64>],
+        a: &[Complex<f64>],
+        b: &[Complex<f64>],
+        len: usize,
+    ) -> usize {
+        let (acc, a, b) = (
+            accum.as_mut_ptr() as *mut f64,
+            a.as_ptr() as *const f64,
+            b.as_ptr() as *const f64,
+        );
+
+        let bins = len - len % 4;
+        for i in (0..bins * 2).step_by(8) {
+            let x = _mm512_loadu_pd(a.add(i));
+            let y = _mm512_loadu_pd(b.add(i));
+
+            let re = _mm512_movedup_pd(x);
+            let im = _mm512_permute_pd::<0b1111_1111>(x);
+            let swapped = _mm512_permute_pd::<0b0101_0101>(y);
+
+            let product = _mm512_fmaddsub_pd(re, y, _mm512_mul_pd(im, swapped));
+            _mm512_storeu_pd(
+                acc.add(i),
+                _mm512_add_pd(_mm512_loadu_pd(acc.add(i)), product),
+            );
+        }
+
+        bins
+    }
+}
+
+/// neon can load pairs straight into separate real and imaginary registers,
+/// so there's no shuffling to do
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use realfft::num_complex::Complex;
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn f32_neon(
+        accum: &mut [Complex<f32>],
+        a: &[Complex<f32>],
+        b: &[Complex<f32>],
+        len: usize,
+    ) -> usize {
+        let (acc, a, b) = (
+            accum.as_mut_ptr() as *mut f32,
+            a.as_ptr() as *const f32,
+            b.as_ptr() as *const f32,
+        );
+
+        let bins = len - len % 4;
+        for i in (0..bins * 2).step_by(8) {
+            let x = vld2q_f32(a.add(i));
+            let y = vld2q_f32(b.add(i));
+            let mut s = vld2q_f32(acc.add(i));
+
+            s.0 = vfmsq_f32(vfmaq_f32(s.0, x.0, y.0), x.1, y.1);
+            s.1 = vfmaq_f32(vfmaq_f32(s.1, x.0, y.1), x.1, y.0);
+            vst2q_f32(acc.add(i), s);
+        }
+
+        bins
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn f64_neon(
+        accum: &mut [Complex<f64>],
+        a: &[Complex<f64>],
+        b: &[Complex<f64>],
+        len: usize,
+    ) -> usize {
+        let (acc, a, b) = (
+            accum.as_mut_ptr() as *mut f64,
+            a.as_ptr() as *const f64,
+            b.as_ptr() as *const f64,
+        );
+
+        let bins = len - len % 2;
+        for i in (0..bins * 2).step_by(4) {
+            let x = vld2q_f64(a.add(i));
+            let y = vld2q_f64(b.add(i));
+            let mut s = vld2q_f64(acc.add(i));
+
+            s.0 = vfmsq_f64(vfmaq_f64(s.0, x.0, y.0), x.1, y.1);
+            s.1 = vfmaq_f64(vfmaq_f64(s.1, x.0, y.1), x.1, y.0);
+            vst2q_f64(acc.add(i), s);
+        }
+
+        bins
+    }
+}
@@ -0,0 +1,308 @@
+use rtrb::{Consumer, Producer, PushError, RingBuffer};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::{
+    conv::{Conv, ConvError},
+    sample::Sample,
+    upconv::FadeCurve,
+};
+
+/*
+a `Conv` can't change its block size, routing or partition once it's running, so to
+change them a new one gets built off the audio thread, with whatever threads and
+buffers it needs, and handed over to be faded to. the old one keeps running until
+the fade is over, and is then handed back to be dropped off the audio thread too
+
+both engines are given the same input during the fade, so they have to have the same
+latency for their outputs to line up. a new block size changes the latency, unless both
+engines have a head set, which takes the latency away altogether
+
+setting a head on the running engine changes its latency too, so that goes through the
+`SwapConv`, which shares the latency with the handle to check the next engine against
+*/
+
+/// how many engines can be waiting to be dropped before the audio thread holds on to them
+const RETIRED_ENGINES: usize = 2;
+
+/// an engine, with the buffers it needs to fade in from the one before it
+struct Staged<T: Sample> {
+    conv: Conv<T>,
+    // the old engine's output during the fade, mixed with the new one's, one max block
+    // for every output either of them has
+    fade_buff: Vec<T>,
+    // stands in for any inputs the old engine has that the host doesn't give us any more
+    zeros: Vec<T>,
+}
+
+/// the audio thread side of a `Conv` that can be swapped for a differently configured one
+/// while it's running, the engines are crossfaded over `fade_len` samples
+pub struct SwapConv<T: Sample = f32> {
+    current: Staged<T>,
+    // the engine being faded out, which stays here after the fade until it can be retired
+    old: Option<Staged<T>>,
+    fade_pos: usize,
+    fade_len: usize,
+    fade_curve: FadeCurve,
+    staged: Consumer<Staged<T>>,
+    retired: Producer<Staged<T>>,
+    latency: Arc<AtomicUsize>,
+}
+
+/// the other side of a `SwapConv`, which stages new engines, and drops the old ones
+pub struct SwapHandle<T: Sample = f32> {
+    staged: Producer<Staged<T>>,
+    retired: Consumer<Staged<T>>,
+    // the most outputs any engine staged has had, which is at least as many as whichever
+    // one the next fades from, even if the last one staged was handed straight back
+    outputs: usize,
+    // the latency every engine has to have, which only changes when a head is set
+    latency: Arc<AtomicUsize>,
+}
+
+impl<T: Sample> SwapConv<T> {
+    /// this function is not real time safe
+    pub fn new(conv: Conv<T>, fade_len: usize, fade_curve: FadeCurve) -> (Self, SwapHandle<T>) {
+        let (staged_prod, staged_cons) = RingBuffer::new(1);
+        let (retired_prod, retired_cons) = RingBuffer::new(RETIRED_ENGINES);
+        let outputs = conv.routing().outputs();
+        let latency = Arc::new(AtomicUsize::new(conv.latency()));
+
+        let swap = Self {
+            current: Staged {
+                conv,
+                fade_buff: vec![],
+                zeros: vec![],
+            },
+            old: None,
+            fade_pos: 0,
+            fade_len: fade_len.max(1),
+            fade_curve,
+            staged: staged_cons,
+            retired: retired_prod,
+            latency: latency.clone(),
+        };
+        let handle = SwapHandle {
+            staged: staged_prod,
+            retired: retired_cons,
+            outputs,
+            latency,
+        };
+
+        (swap, handle)
+    }
+
+    /// the engine that's running, or being faded to
+    pub fn conv(&self) -> &Conv<T> {
+        &self.current.conv
+    }
+
+    /// for updating the filter, or anything else that should go to the engine
+    /// being faded to, the one being faded out is left as it is
+    ///
+    /// heads have to be set with `set_head` instead, which keeps the latency
+    /// the next engine is checked against in step
+    ///
+    /// an engine that's been staged is swapped in first if it can be, so
+    /// nothing meant for it goes to the one it replaces instead
+    pub fn conv_mut(&mut self) -> &mut Conv<T> {
+        self.retire();
+        self.swap_in();
+        &mut self.current.conv
+    }
+
+    /// `Conv::set_head` on the running engine, which changes its latency, so this returns
+    /// `ConvError::SwapPending` while an engine is staged or being faded to, since that one
+    /// was checked against the latency from before
+    ///
+    /// this function is not real time safe
+    pub fn set_head(&mut self, head: &[T]) -> Result<(), ConvError> {
+        self.retire();
+        self.swap_in();
+        if self.old.is_some() || !self.staged.is_empty() {
+            return Err(ConvError::SwapPending);
+        }
+
+        self.current.conv.set_head(head)?;
+        self.latency
+            .store(self.current.conv.latency(), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// whether the last engine staged is being faded to
+    pub fn is_fading(&self) -> bool {
+        self.old.is_some() && self.fade_pos < self.fade_len
+    }
+
+    /// resets the engine that's running, and ends any fade, so only it is heard
+    ///
+    /// this function is real time safe
+    pub fn reset(&mut self) {
+        self.current.conv.reset();
+        self.fade_pos = self.fade_len;
+        self.retire();
+    }
+
+    /// `Conv::process_in_place` for whichever engine is running, which starts fading
+    /// to the last engine staged if there is one, the buffers have to be laid out
+    /// for the new engine as soon as it's been staged, and can be any length
+    ///
+    /// the old engine is given the inputs it shares with the new one, and silence for
+    /// the rest, and any outputs the new engine doesn't have are faded out with it
+    ///
+    /// this function is real time safe
+    pub fn process_in_place(&mut self, buffers: &mut [&mut [T]]) {
+        self.retire();
+        self.swap_in();
+
+        let Some(old) = self.old.as_mut().filter(|_| self.fade_pos < self.fade_len) else {
+            self.current.conv.process_in_place(buffers);
+            return;
+        };
+        let Staged {
+            conv,
+            fade_buff,
+            zeros,
+        } = &mut self.current;
+
+        // the fade is done a block at a time that both engines can take, the old engine's
+        // output goes in the fade buffer and the new one's is mixed in with it there,
+        // since the buffers are still being read from until both have been run
+        let len = buffers.first().map_or(0, |b| b.len());
+        let stride = conv.max_block_size();
+        let chunk = stride.min(old.conv.max_block_size());
+        let old_outputs = old.conv.routing().outputs();
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(chunk);
+            let out = old.conv.process(
+                buffers
+                    .iter()
+                    .map(|b| &b[done..done + n])
+                    .chain(std::iter::repeat(&zeros[0..n])),
+            );
+            for (fade_channel, o) in fade_buff.chunks_exact_mut(stride).zip(out) {
+                fade_channel[0..n].copy_from_slice(o);
+            }
+            for fade_channel in fade_buff.chunks_exact_mut(stride).skip(old_outputs) {
+                fade_channel[0..n].fill(T::zero());
+            }
+
+            let out = conv.process(buffers.iter().map(|b| &b[done..done + n]));
+            for (fade_channel, o) in fade_buff.chunks_exact_mut(stride).zip(out) {
+                for (j, (f, s)) in fade_channel[0..n].iter_mut().zip(o).enumerate() {
+                    let position = (self.fade_pos + done + j).min(self.fade_len);
+                    let (old_gain, new_gain) = self.fade_curve.gains(
+                        T::from_usize(position).unwrap() / T::from_usize(self.fade_len).unwrap(),
+                    );
+                    *f = *s * new_gain + *f * old_gain;
+                }
+            }
+
+            for (buffer, fade_channel) in buffers
+                .iter_mut()
+                .zip(fade_buff.chunks_exact(stride))
+                .take(conv.routing().outputs())
+            {
+                buffer[done..done + n].copy_from_slice(&fade_channel[0..n]);
+            }
+            done += n;
+        }
+
+        self.fade_pos += len;
+        self.retire();
+    }
+
+    /// starts fading to the engine that's been staged, once the last fade is over
+    /// and the engine it faded from has been retired
+    fn swap_in(&mut self) {
+        if self.old.is_some() {
+            return;
+        }
+        // a head set between the handle checking an engine and staging it changes
+        // the latency under it, so that engine goes straight back to be dropped
+        let latency = self.current.conv.latency();
+        match self.staged.peek() {
+            Ok(staged) if staged.conv.latency() != latency => {
+                if !self.retired.is_full() {
+                    let _ = self.retired.push(self.staged.pop().unwrap());
+                }
+            }
+            Ok(_) => {
+                let staged = self.staged.pop().unwrap();
+                self.old = Some(std::mem::replace(&mut self.current, staged));
+                self.fade_pos = 0;
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// hands the old engine back to be dropped once its fade is over,
+    /// if there's no room for it we hang on to it and try again next time
+    fn retire(&mut self) {
+        if self.fade_pos < self.fade_len {
+            return;
+        }
+        if let Some(old) = self.old.take() {
+            if let Err(PushError::Full(old)) = self.retired.push(old) {
+                self.old = Some(old);
+            }
+        }
+    }
+}
+
+impl<T: Sample> SwapHandle<T> {
+    /// hands over an engine for the `SwapConv` to fade to, which happens at the start of
+    /// its next process once any fade before it is over, the buffers given to it have to
+    /// be laid out for the new engine from then on
+    ///
+    /// if the last engine staged hasn't been swapped in yet this returns
+    /// `ConvError::SwapPending`, and if `conv` doesn't have the same latency as the
+    /// engines before it, heads and all, it returns `ConvError::LatencyMismatch`,
+    /// either way `conv` is dropped
+    ///
+    /// this function is not real time safe
+    pub fn stage(&mut self, conv: Conv<T>) -> Result<(), ConvError> {
+        self.collect();
+        if self.staged.is_full() {
+            return Err(ConvError::SwapPending);
+        }
+        let latency = self.latency.load(Ordering::Acquire);
+        if conv.latency() != latency {
+            return Err(ConvError::LatencyMismatch {
+                expected: latency,
+                actual: conv.latency(),
+            });
+        }
+
+        let (max_block_size, outputs) = (conv.max_block_size(), conv.routing().outputs());
+        let staged = Staged {
+            conv,
+            fade_buff: vec![T::zero(); max_block_size * self.outputs.max(outputs)],
+            zeros: vec![T::zero(); max_block_size],
+        };
+        self.staged
+            .push(staged)
+            .map_err(|_| ConvError::SwapPending)?;
+        self.outputs = self.outputs.max(outputs);
+
+        Ok(())
+    }
+
+    /// drops the engines that have been swapped out, this is done
+    /// every time an engine is staged, and when the handle is dropped
+    ///
+    /// this function is not real time safe
+    pub fn collect(&mut self) {
+        while self.retired.pop().is_ok() {}
+    }
+}
+
+impl<T: Sample> Drop for SwapHandle<T> {
+    fn drop(&mut self) {
+        self.collect();
+    }
+}
@@ -1,50 +1,237 @@
 use realfft::RealFftPlanner;
 use realfft::{num_complex::Complex, ComplexToReal, RealToComplex};
-use std::f32::consts::PI;
-use std::sync::Arc;
-
-pub struct UPConv {
-    fft: Arc<dyn RealToComplex<f32>>,
-    ifft: Arc<dyn ComplexToReal<f32>>,
-    input_buff: Vec<f32>,
-    input_fft_buff: Vec<f32>,
-    output_buff: Vec<f32>,
-    output_fft_buff: Vec<f32>,
-    filter: Vec<Complex<f32>>,
-    fdl: Vec<Complex<f32>>,
-    accumulation_buffer: Vec<Complex<f32>>,
-    new_spectrum_buff: Vec<Complex<f32>>,
+use std::{mem::size_of, sync::Arc};
+
+use crate::{conv::ConvError, sample::Sample, simd::Kernel};
+
+/// the shape of the crossfade used when a filter is swapped out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FadeCurve {
+    /// half a cosine period, smooth at both ends
+    #[default]
+    RaisedCosine,
+    /// sine/cosine gains, keeps the summed power constant for uncorrelated outputs
+    EqualPower,
+    Linear,
+}
+
+impl FadeCurve {
+    /// returns the (old, new) gains at `position`, which goes from 0 to 1 over the fade
+    pub fn gains<T: Sample>(&self, position: T) -> (T, T) {
+        let half = T::from_f32(0.5).unwrap();
+        match self {
+            Self::RaisedCosine => {
+                let new = half - T::cos(position * T::PI()) * half;
+                (T::one() - new, new)
+            }
+            Self::EqualPower => (
+                T::cos(position * T::FRAC_PI_2()),
+                T::sin(position * T::FRAC_PI_2()),
+            ),
+            Self::Linear => (T::one() - position, position),
+        }
+    }
+}
+
+/// which inputs go through which filters to which outputs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Routing {
+    /// input i goes through filter i to output i
+    Parallel(usize),
+    /// every input goes to every output, through filter `output * inputs + input`,
+    /// so a true stereo filter is laid out LL, RL, LR, RR
+    Matrix { inputs: usize, outputs: usize },
+}
+
+impl Routing {
+    pub fn inputs(&self) -> usize {
+        match self {
+            Self::Parallel(channels) => *channels,
+            Self::Matrix { inputs, .. } => *inputs,
+        }
+    }
+
+    pub fn outputs(&self) -> usize {
+        match self {
+            Self::Parallel(channels) => *channels,
+            Self::Matrix { outputs, .. } => *outputs,
+        }
+    }
+
+    /// the number of filters, which is how many channels `process_filter` has to be given
+    pub fn paths(&self) -> usize {
+        match self {
+            Self::Parallel(channels) => *channels,
+            Self::Matrix { inputs, outputs } => inputs * outputs,
+        }
+    }
+
+    /// the (input, output) a filter goes between
+    pub fn path(&self, path: usize) -> (usize, usize) {
+        match self {
+            Self::Parallel(_) => (path, path),
+            Self::Matrix { inputs, .. } => (path % inputs, path / inputs),
+        }
+    }
+}
+
+/// how the output of each inverse fft is made into a block of output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overlap {
+    /// each fft takes the last fft size worth of input, and only the last
+    /// block of what comes out is kept, the rest of it has wrapped around
+    #[default]
+    Save,
+    /// each fft takes one block of input padded out with zeros, and what
+    /// comes out is added onto the tails of the blocks before it
+    Add,
+}
+
+/// how a segment does its convolution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scheme {
+    pub overlap: Overlap,
+    /// the fft size as a multiple of the block size, which is at least 2. anything
+    /// over 2 is padding, which costs more, but leaves room for the spectra to be
+    /// changed without what comes out wrapping around
+    pub fft_ratio: usize,
+}
+
+impl Default for Scheme {
+    fn default() -> Self {
+        Self {
+            overlap: Overlap::Save,
+            fft_ratio: 2,
+        }
+    }
+}
+
+/// a uniformly partitioned convolution, with its output at unity gain
+pub struct UPConv<T: Sample = f32> {
+    fft: Arc<dyn RealToComplex<T>>,
+    ifft: Arc<dyn ComplexToReal<T>>,
+    input_buff: Vec<T>,
+    input_fft_buff: Vec<T>,
+    output_buff: Vec<T>,
+    output_fft_buff: Vec<T>,
+    // realfft needs somewhere to work for sizes that aren't a power of 2,
+    // big enough for either direction
+    scratch: Vec<Complex<T>>,
+    // the tails of the blocks so far for overlap add, one fft size per output,
+    // and the same for the old filter, these are empty for overlap save
+    tail_buff: Vec<T>,
+    old_tail_buff: Vec<T>,
+    // the old filter's output for a block while it's being faded out
+    fade_buff: Vec<T>,
+    // one filter per path, but only one fdl per input, which every path from it shares
+    filter: Vec<Complex<T>>,
+    fdl: Vec<Complex<T>>,
+    // the fdl is a ring, this is the slot the newest spectrum is in, and the one
+    // before it is `num_blocks - 1` slots on, so nothing has to be moved along
+    fdl_pos: usize,
+    // one accumulation buffer per output for the new filter, and one for the old,
+    // so the work for a block can be done a bit at a time, and every path
+    // to an output is summed before its one inverse fft
+    accumulation_buffer: Vec<Complex<T>>,
+    old_accumulation_buffer: Vec<Complex<T>>,
     block_size: usize,
-    channels: usize,
+    fft_size: usize,
+    overlap: Overlap,
+    routing: Routing,
     num_blocks: usize,
-    old_filter: (bool, Vec<Complex<f32>>),
+    old_filter: (bool, Vec<Complex<T>>),
+    fade_curve: FadeCurve,
+    // how many samples have been put out since the filter was updated, the fade
+    // starts `fade_start` samples in, and the new filter is only run from `new_start`
+    fade_pos: usize,
+    fade_start: usize,
+    new_start: usize,
+    fade_len: usize,
+    // how many blocks of each filter have anything in them, every path's blocks after
+    // these are all zeros, so their multiply accumulates are skipped
+    active_blocks: usize,
+    old_active_blocks: usize,
+    // the multiply accumulate kernel, picked for this cpu when the upconv is made
+    kernel: Kernel,
 }
 
-impl UPConv {
+impl<T: Sample> UPConv<T> {
+    /// `starting_filter` can have fewer blocks per path than `num_blocks`,
+    /// the same as with `update_filter`
     pub fn new(
         block_size: usize,
-        starting_filter: &[Complex<f32>],
-        channels: usize,
+        starting_filter: &[Complex<T>],
+        routing: Routing,
         num_blocks: usize,
+        fade_curve: FadeCurve,
+        fade_len: usize,
+        scheme: Scheme,
     ) -> Self {
-        let mut planner = RealFftPlanner::<f32>::new();
-        let fft = planner.plan_fft_forward(block_size * 2);
-        let ifft = planner.plan_fft_inverse(block_size * 2);
+        let fft_size = block_size * scheme.fft_ratio;
+        let spectrum_len = fft_size / 2 + 1;
+
+        let mut planner = RealFftPlanner::<T>::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let ifft = planner.plan_fft_inverse(fft_size);
 
         let input_fft_buff = fft.make_input_vec();
         let output_fft_buff = ifft.make_output_vec();
-        let accumulation_buffer = ifft.make_input_vec();
-        let new_spectrum_buff = fft.make_output_vec();
+        let scratch = vec![
+            Complex {
+                re: T::zero(),
+                im: T::zero()
+            };
+            fft.get_scratch_len().max(ifft.get_scratch_len())
+        ];
 
-        let input_buff = vec![0.0; block_size * 2 * channels];
-        let output_buff = vec![0.0; block_size * channels];
+        let input_buff = vec![T::zero(); fft_size * routing.inputs()];
+        let output_buff = vec![T::zero(); block_size * routing.outputs()];
 
-        let old_filter =
-            vec![Complex { re: 0.0, im: 0.0 }; (block_size + 1) * num_blocks * channels];
+        let tail_len = match scheme.overlap {
+            Overlap::Save => 0,
+            Overlap::Add => fft_size * routing.outputs(),
+        };
 
-        let fdl = vec![Complex { re: 0.0, im: 0.0 }; (block_size + 1) * num_blocks * channels];
+        let accumulation_buffer = vec![
+            Complex {
+                re: T::zero(),
+                im: T::zero()
+            };
+            spectrum_len * routing.outputs()
+        ];
+        let old_accumulation_buffer = vec![
+            Complex {
+                re: T::zero(),
+                im: T::zero()
+            };
+            spectrum_len * routing.outputs()
+        ];
 
-        Self {
+        let old_filter = vec![
+            Complex {
+                re: T::zero(),
+                im: T::zero()
+            };
+            spectrum_len * num_blocks * routing.paths()
+        ];
+
+        let fdl = vec![
+            Complex {
+                re: T::zero(),
+                im: T::zero()
+            };
+            spectrum_len * num_blocks * routing.inputs()
+        ];
+
+        let filter = vec![
+            Complex {
+                re: T::zero(),
+                im: T::zero()
+            };
+            spectrum_len * num_blocks * routing.paths()
+        ];
+
+        let mut upconv = Self {
             fft,
             ifft,
             block_size,
@@ -52,126 +239,382 @@ impl UPConv {
             input_fft_buff,
             output_buff,
             output_fft_buff,
-            filter: Vec::from(starting_filter),
+            scratch,
+            tail_buff: vec![T::zero(); tail_len],
+            old_tail_buff: vec![T::zero(); tail_len],
+            fade_buff: vec![T::zero(); block_size],
+            filter,
             fdl,
+            fdl_pos: 0,
             accumulation_buffer,
-            new_spectrum_buff,
-            channels,
+            old_accumulation_buffer,
+            fft_size,
+            overlap: scheme.overlap,
+            routing,
             num_blocks,
             old_filter: (false, old_filter),
-        }
+            fade_curve,
+            fade_pos: 0,
+            fade_start: 0,
+            new_start: 0,
+            fade_len: fade_len.max(1),
+            active_blocks: 0,
+            old_active_blocks: 0,
+            kernel: Kernel::best(),
+        };
+        upconv.load_filter(starting_filter);
+
+        upconv
     }
 
-    pub fn update_filter(&mut self, new_filter: &[Complex<f32>]) {
-        self.old_filter.1.copy_from_slice(&self.filter);
+    /// the old filter keeps being convolved until the fade is over, and the output
+    /// fades from it to the new one over `fade_len` samples, starting `fade_start`
+    /// samples after the start of the next block, which can be any number of blocks on
+    ///
+    /// the new filter is only run from the block the fade starts in, or with overlap add
+    /// from far enough before it for its tails to be right by then, if there's room
+    ///
+    /// `new_filter` is laid out like the filter the upconv was made with, but it can
+    /// have fewer blocks per path, and the blocks it doesn't have are zeros, so a
+    /// shorter filter can be swapped in without anything being allocated
+    ///
+    /// if the last update hasn't finished fading in, nothing is changed and this returns
+    /// `ConvError::FadePending`, fading from partway through a fade would jump
+    pub fn update_filter(
+        &mut self,
+        new_filter: &[Complex<T>],
+        fade_start: usize,
+    ) -> Result<(), ConvError> {
+        if self.old_filter.0 {
+            return Err(ConvError::FadePending);
+        }
+
+        // the filter that's running becomes the old one, and the buffer
+        // the last old filter was in takes the new one
+        std::mem::swap(&mut self.filter, &mut self.old_filter.1);
+        self.old_active_blocks = self.active_blocks;
+        // the tails so far are the old filter's. the new filter's output starts
+        // from them too, which is only off until they've run out
+        self.old_tail_buff.copy_from_slice(&self.tail_buff);
 
-        self.filter.copy_from_slice(new_filter);
+        self.load_filter(new_filter);
 
+        // a filter block is one block long, so an overlap add tail only reaches the next block
+        let tail_blocks = match self.overlap {
+            Overlap::Save => 0,
+            Overlap::Add => 1,
+        };
         self.old_filter.0 = true;
+        self.fade_pos = 0;
+        self.fade_start = fade_start;
+        self.new_start =
+            (fade_start / self.block_size).saturating_sub(tail_blocks) * self.block_size;
+
+        Ok(())
+    }
+
+    /// whether the new filter is being run this block, it isn't before its fade gets close
+    fn new_running(&self) -> bool {
+        !self.old_filter.0 || self.fade_pos >= self.new_start
+    }
+
+    /// copies in each path's blocks, zeros the rest, and works out how many
+    /// blocks are left once the empty ones on the end are taken off, this
+    /// swaps the filter straight away, so it's only for when nothing's playing
+    pub(crate) fn load_filter(&mut self, new_filter: &[Complex<T>]) {
+        let zero = Complex {
+            re: T::zero(),
+            im: T::zero(),
+        };
+        let spectrum_len = self.fft_size / 2 + 1;
+        let path_len = spectrum_len * self.num_blocks;
+        let new_path_len = new_filter.len() / self.routing.paths();
+        debug_assert!(new_path_len <= path_len && new_path_len.is_multiple_of(spectrum_len));
+
+        for (p, path) in self.filter.chunks_exact_mut(path_len).enumerate() {
+            path[0..new_path_len]
+                .copy_from_slice(&new_filter[new_path_len * p..new_path_len * (p + 1)]);
+            path[new_path_len..].fill(zero);
+        }
+
+        self.active_blocks = (0..new_path_len / spectrum_len)
+            .rev()
+            .find(|block| {
+                self.filter.chunks_exact(path_len).any(|path| {
+                    path[spectrum_len * block..spectrum_len * (block + 1)]
+                        .iter()
+                        .any(|bin| *bin != zero)
+                })
+            })
+            .map_or(0, |block| block + 1);
+    }
+
+    /// clears the inputs, the fdl and the outputs, so nothing from before
+    /// carries on into the next block, and ends any filter fade, so only
+    /// the newest filter is heard, even if its fade hadn't started yet
+    ///
+    /// this function is real time safe
+    pub fn reset(&mut self) {
+        let zero = Complex {
+            re: T::zero(),
+            im: T::zero(),
+        };
+        self.input_buff.fill(T::zero());
+        self.output_buff.fill(T::zero());
+        self.tail_buff.fill(T::zero());
+        self.old_tail_buff.fill(T::zero());
+        self.fdl.fill(zero);
+        self.fdl_pos = 0;
+        self.accumulation_buffer.fill(zero);
+        self.old_accumulation_buffer.fill(zero);
+
+        self.old_filter.0 = false;
+        self.fade_pos = 0;
     }
 
     /// block is a slice of channel slices, as opposed to a slice of sample slices,
-    /// so there will be one block size slice of samples per channel in block
+    /// so there will be one block size slice of samples per input in block,
+    /// and the output has one block per output one after the other
     pub fn process_block<'blocks>(
         &mut self,
-        channel_blocks: impl Iterator<Item = &'blocks [f32]>,
-    ) -> &[f32] {
-        // move the inputs over by one block and add the new block on the end
-        // iterate over everything by channel
-        for ((((in_channel, out_channel), block_channel), fdl_channel), filter_channel) in self
+        channel_blocks: impl Iterator<Item = &'blocks [T]>,
+    ) -> &[T] {
+        self.load_block(channel_blocks);
+
+        for task in 0..self.tasks() {
+            self.run_task(task);
+        }
+
+        self.finish_block()
+    }
+
+    /*
+    the work for a block can also be done in steps, which is what `process_block` does
+    all in one go: first `load_block`, then every task from 0 to `tasks()` in order,
+    then `finish_block` to get the output. the tasks are the forward fft of every input,
+    then a multiply accumulate for every block of every path's filter, then the inverse
+    fft of every output
+    */
+
+    /// for overlap save this moves the inputs over by one block and adds the new block
+    /// on the end, for overlap add the new block goes at the start, and the rest is zeros
+    pub fn load_block<'blocks>(&mut self, channel_blocks: impl Iterator<Item = &'blocks [T]>) {
+        let (block_size, fft_size) = (self.block_size, self.fft_size);
+        for (in_channel, block_channel) in self
             .input_buff
-            .chunks_exact_mut(self.block_size * 2)
-            .zip(self.output_buff.chunks_exact_mut(self.block_size))
+            .chunks_exact_mut(fft_size)
             .zip(channel_blocks)
-            .zip(
-                self.fdl
-                    .chunks_exact_mut((self.block_size + 1) * self.num_blocks),
-            )
-            .zip(
-                self.filter
-                    .chunks_exact_mut((self.block_size + 1) * self.num_blocks),
-            )
         {
-            in_channel.copy_within(self.block_size..self.block_size * 2, 0);
-            in_channel[self.block_size..self.block_size * 2].copy_from_slice(block_channel);
-
-            self.input_fft_buff.copy_from_slice(&in_channel);
-            self.fft
-                .process_with_scratch(
-                    &mut self.input_fft_buff,
-                    &mut self.new_spectrum_buff,
-                    &mut [],
-                )
-                .unwrap();
-
-            fdl_channel.copy_within(
-                0..fdl_channel.len() - (self.block_size + 1),
-                self.block_size + 1,
-            );
-            fdl_channel[0..self.block_size + 1].copy_from_slice(&self.new_spectrum_buff);
-
-            self.new_spectrum_buff.fill(Complex { re: 0.0, im: 0.0 });
-            self.accumulation_buffer.fill(Complex { re: 0.0, im: 0.0 });
-            self.output_fft_buff.fill(0.0);
-
-            for (filter_block, fdl_block) in filter_channel
-                .chunks_exact(self.block_size + 1)
-                .zip(fdl_channel.chunks_exact(self.block_size + 1))
-            {
-                for ((filter_sample, fdl_sample), accum_sample) in filter_block
-                    .iter()
-                    .zip(fdl_block)
-                    .zip(&mut self.accumulation_buffer)
-                {
-                    *accum_sample += filter_sample * fdl_sample;
+            match self.overlap {
+                Overlap::Save => {
+                    in_channel.copy_within(block_size..fft_size, 0);
+                    in_channel[fft_size - block_size..fft_size].copy_from_slice(block_channel);
                 }
+                Overlap::Add => in_channel[0..block_size].copy_from_slice(block_channel),
             }
+        }
+
+        // the oldest spectrum's slot is the one the new spectra go in,
+        // which every input shares
+        self.fdl_pos = (self.fdl_pos + self.num_blocks - 1) % self.num_blocks;
+
+        let zero = Complex {
+            re: T::zero(),
+            im: T::zero(),
+        };
+        self.accumulation_buffer.fill(zero);
+        self.old_accumulation_buffer.fill(zero);
+    }
+
+    /// the number of steps the work for a block is split into
+    pub fn tasks(&self) -> usize {
+        self.routing.inputs() + self.routing.paths() * self.num_blocks + self.routing.outputs()
+    }
+
+    pub fn run_task(&mut self, task: usize) {
+        let inputs = self.routing.inputs();
+        let macs = self.routing.paths() * self.num_blocks;
+        if task < inputs {
+            self.forward(task);
+        } else if task < inputs + macs {
+            let mac = task - inputs;
+            self.accumulate(mac / self.num_blocks, mac % self.num_blocks);
+        } else {
+            self.inverse(task - inputs - macs);
+        }
+    }
 
-            self.ifft
-                .process_with_scratch(
-                    &mut self.accumulation_buffer,
-                    &mut self.output_fft_buff,
-                    &mut [],
-                )
-                .unwrap();
-
-            out_channel
-                .copy_from_slice(&self.output_fft_buff[self.block_size..self.block_size * 2]);
-
-            if self.old_filter.0 {
-                // TODO
-                // let old = &self.old_filter.1[i];
-                // self.accumulation_buffer.fill(Complex { re: 0.0, im: 0.0 });
-
-                // for (filter_block, fdl_block) in old.chunks(self.block_size + 1).zip(&*fdl) {
-                //     for i in 0..self.block_size + 1 {
-                //         self.accumulation_buffer[i] += filter_block[i] * fdl_block[i];
-                //     }
-                // }
-
-                // self.ifft
-                //     .process_with_scratch(
-                //         &mut self.accumulation_buffer,
-                //         &mut self.output_fft_buff,
-                //         &mut [],
-                //     )
-                //     .unwrap();
-
-                // let mut j = 0;
-                // for (o, f) in out
-                //     .iter_mut()
-                //     .zip(&self.output_fft_buff[self.block_size..self.block_size * 2])
-                // {
-                //     let f_in = f32::cos(j as f32 * PI / self.block_size as f32) * 0.5 + 0.5;
-                //     let f_out = 1.0 - f_in;
-                //     *o *= f_in;
-                //     *o += f * f_out;
-                //     j += 1;
-                // }
-                // self.old_filter.0 = false;
+    /// the bytes taken up by the buffers, the filters and the fdl
+    pub fn memory_usage(&self) -> usize {
+        let real = self.input_buff.len()
+            + self.input_fft_buff.len()
+            + self.output_buff.len()
+            + self.output_fft_buff.len()
+            + self.tail_buff.len()
+            + self.old_tail_buff.len()
+            + self.fade_buff.len();
+        let complex = self.filter.len()
+            + self.fdl.len()
+            + self.accumulation_buffer.len()
+            + self.old_accumulation_buffer.len()
+            + self.old_filter.1.len()
+            + self.scratch.len();
+
+        real * size_of::<T>() + complex * size_of::<Complex<T>>()
+    }
+
+    /// the output for the block, once every task has been run
+    pub fn finish_block(&mut self) -> &[T] {
+        if self.old_filter.0 {
+            self.fade_pos += self.block_size;
+            if self.fade_pos >= self.fade_start + self.fade_len {
+                self.old_filter.0 = false;
             }
         }
 
         &self.output_buff
     }
+
+    fn forward(&mut self, input: usize) {
+        let spectrum_len = self.fft_size / 2 + 1;
+        let fdl_channel = &mut self.fdl
+            [spectrum_len * self.num_blocks * input..spectrum_len * self.num_blocks * (input + 1)];
+
+        self.input_fft_buff
+            .copy_from_slice(&self.input_buff[self.fft_size * input..self.fft_size * (input + 1)]);
+
+        // the new spectrum goes over the oldest one
+        let slot = spectrum_len * self.fdl_pos;
+        self.fft
+            .process_with_scratch(
+                &mut self.input_fft_buff,
+                &mut fdl_channel[slot..slot + spectrum_len],
+                &mut self.scratch,
+            )
+            .unwrap();
+    }
+
+    fn accumulate(&mut self, path: usize, block: usize) {
+        let spectrum_len = self.fft_size / 2 + 1;
+        let (input, output) = self.routing.path(path);
+        let start = spectrum_len * (self.num_blocks * path + block);
+        // block `block` of the filter goes with the spectrum from `block` blocks ago
+        let slot = (self.fdl_pos + block) % self.num_blocks;
+        let fdl_start = spectrum_len * (self.num_blocks * input + slot);
+
+        let fdl_block = &self.fdl[fdl_start..fdl_start + spectrum_len];
+
+        if block < self.active_blocks && self.new_running() {
+            let accum =
+                &mut self.accumulation_buffer[spectrum_len * output..spectrum_len * (output + 1)];
+
+            T::mac(
+                self.kernel,
+                accum,
+                &self.filter[start..start + spectrum_len],
+                fdl_block,
+            );
+        }
+
+        if self.old_filter.0 && block < self.old_active_blocks {
+            let old_accum = &mut self.old_accumulation_buffer
+                [spectrum_len * output..spectrum_len * (output + 1)];
+
+            T::mac(
+                self.kernel,
+                old_accum,
+                &self.old_filter.1[start..start + spectrum_len],
+                fdl_block,
+            );
+        }
+    }
+
+    fn inverse(&mut self, output: usize) {
+        let spectrum_len = self.fft_size / 2 + 1;
+        let block_size = self.block_size;
+        let tail_len = self.tail_buff.len() / self.routing.outputs();
+        let new_running = self.new_running();
+        let out_channel = &mut self.output_buff[block_size * output..block_size * (output + 1)];
+
+        // with nothing in the filter the inverse fft would only give zeros,
+        // but they still have to go through, an overlap add tail has to run out
+        if new_running {
+            if self.active_blocks > 0 {
+                self.ifft
+                    .process_with_scratch(
+                        &mut self.accumulation_buffer
+                            [spectrum_len * output..spectrum_len * (output + 1)],
+                        &mut self.output_fft_buff,
+                        &mut self.scratch,
+                    )
+                    .unwrap();
+            } else {
+                self.output_fft_buff.fill(T::zero());
+            }
+            overlap_block(
+                self.overlap,
+                &self.output_fft_buff,
+                &mut self.tail_buff[tail_len * output..tail_len * (output + 1)],
+                out_channel,
+            );
+        } else {
+            // the new filter's gain is zero until its fade starts anyway
+            out_channel.fill(T::zero());
+        }
+
+        if self.old_filter.0 {
+            if self.old_active_blocks > 0 {
+                self.ifft
+                    .process_with_scratch(
+                        &mut self.old_accumulation_buffer
+                            [spectrum_len * output..spectrum_len * (output + 1)],
+                        &mut self.output_fft_buff,
+                        &mut self.scratch,
+                    )
+                    .unwrap();
+            } else {
+                self.output_fft_buff.fill(T::zero());
+            }
+            overlap_block(
+                self.overlap,
+                &self.output_fft_buff,
+                &mut self.old_tail_buff[tail_len * output..tail_len * (output + 1)],
+                &mut self.fade_buff,
+            );
+
+            for (j, (o, old)) in out_channel.iter_mut().zip(&self.fade_buff).enumerate() {
+                let position = (self.fade_pos + j)
+                    .saturating_sub(self.fade_start)
+                    .min(self.fade_len);
+                let (old_gain, new_gain) = self.fade_curve.gains(
+                    T::from_usize(position).unwrap() / T::from_usize(self.fade_len).unwrap(),
+                );
+                *o = *o * new_gain + *old * old_gain;
+            }
+        }
+    }
+}
+
+/// turns what came out of an inverse fft into the next block of output
+fn overlap_block<T: Sample>(overlap: Overlap, fft_out: &[T], tail: &mut [T], out: &mut [T]) {
+    let (fft_size, block_size) = (fft_out.len(), out.len());
+    // realfft's inverse doesn't normalize, so a round trip comes out scaled by the
+    // fft size, which is taken off here so the output is at unity gain
+    let norm = T::one() / T::from_usize(fft_size).unwrap();
+
+    match overlap {
+        Overlap::Save => {
+            for (o, x) in out.iter_mut().zip(&fft_out[fft_size - block_size..]) {
+                *o = *x * norm;
+            }
+        }
+        Overlap::Add => {
+            for (t, x) in tail.iter_mut().zip(fft_out) {
+                *t += *x * norm;
+            }
+            out.copy_from_slice(&tail[0..block_size]);
+            tail.copy_within(block_size..fft_size, 0);
+            tail[fft_size - block_size..fft_size].fill(T::zero());
+        }
+    }
 }
@@ -0,0 +1,110 @@
+use convrs::{
+    conv::{ConvBuilder, ConvError},
+    filter::ProcessedFilter,
+    helpers::process_filter,
+    partition::Constraints,
+    upconv::Routing,
+};
+
+#[test]
+fn misconfiguration_is_caught_up_front() {
+    let filter = vec![vec![0.5f32; 1000]; 2];
+
+    assert_eq!(
+        ConvBuilder::new(0).build(filter.clone()).err(),
+        Some(ConvError::ZeroBlockSize)
+    );
+    assert_eq!(
+        ConvBuilder::new(64).channels(0).build(filter.clone()).err(),
+        Some(ConvError::NoChannels)
+    );
+    assert_eq!(
+        ConvBuilder::new(64)
+            .routing(Routing::Matrix {
+                inputs: 2,
+                outputs: 2
+            })
+            .build(filter.clone())
+            .err(),
+        Some(ConvError::FilterCount {
+            expected: 4,
+            actual: 2
+        })
+    );
+    assert_eq!(
+        ConvBuilder::new(64)
+            .partition(&[(64, 8)])
+            .build(filter.clone())
+            .err(),
+        Some(ConvError::FilterTooLong {
+            len: 1000,
+            capacity: 512
+        })
+    );
+    assert_eq!(
+        ConvBuilder::new(64)
+            .partition(&[(128, 8)])
+            .build(filter.clone())
+            .err(),
+        Some(ConvError::BlockSizeMismatch {
+            block_size: 64,
+            first_segment: 128
+        })
+    );
+    assert_eq!(
+        ConvBuilder::new(64)
+            .planner(Constraints {
+                headroom: 0,
+                ..Default::default()
+            })
+            .build(filter.clone())
+            .err(),
+        Some(ConvError::NoHeadroom)
+    );
+
+    let processed = process_filter(filter.clone(), &[(64, 16)]).unwrap();
+    // the taps past the end of the partition aren't quietly dropped
+    assert_eq!(
+        process_filter(filter.clone(), &[(64, 8)]).err(),
+        Some(ConvError::FilterTooLong {
+            len: 1000,
+            capacity: 512
+        })
+    );
+    assert_eq!(
+        ProcessedFilter::from_raw(&[(64, 16)], 2, 2000, processed.data().to_vec()).err(),
+        Some(ConvError::FilterTooLong {
+            len: 2000,
+            capacity: 1024
+        })
+    );
+    assert_eq!(
+        ConvBuilder::new(64)
+            .channels(1)
+            .build_processed(&processed)
+            .err(),
+        Some(ConvError::FilterCount {
+            expected: 1,
+            actual: 2
+        })
+    );
+    assert_eq!(
+        ConvBuilder::new(64)
+            .partition(&[(64, 4), (128, 6)])
+            .build_processed(&processed)
+            .err(),
+        Some(ConvError::PartitionMismatch)
+    );
+    assert!(ConvBuilder::new(64).build_processed(&processed).is_ok());
+
+    let mut conv = ConvBuilder::new(64)
+        .max_block_size(100)
+        .fade_len(256)
+        .build(filter)
+        .unwrap();
+    let block = vec![0.0f32; 100];
+    assert_eq!(
+        conv.process([&block[..], &block[..]].into_iter()).count(),
+        2
+    );
+}
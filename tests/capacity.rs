@@ -0,0 +1,87 @@
+use convrs::{
+    conv::{ConvBuilder, ConvError, Scheduling},
+    helpers::process_filter,
+    partition::cover,
+};
+
+mod common;
+use common::{decaying, direct_conv};
+
+#[test]
+fn shorter_and_longer_filters_fit_the_capacity() {
+    let signal: Vec<f64> = (0..16384)
+        .map(|i| (i as f64 * 0.017).sin() + ((i * 11) % 17) as f64 / 17.0)
+        .collect();
+    let filters = [
+        decaying(700, 0.11, 1500.0),
+        decaying(6000, 0.07, 1500.0),
+        decaying(300, 0.23, 1500.0),
+    ];
+    let controls: Vec<Vec<f64>> = filters.iter().map(|f| direct_conv(&signal, f)).collect();
+
+    for scheduling in [Scheduling::Offline, Scheduling::Distributed] {
+        let mut conv = ConvBuilder::new(32)
+            .channels(1)
+            .max_filter_len(6000)
+            .scheduling(scheduling)
+            .fade_len(32)
+            .build(vec![filters[0].clone()])
+            .unwrap();
+        assert!(conv.capacity() >= 6000);
+
+        // every filter is only processed for as much of the partition as it needs,
+        // and the output is the exact convolution once every segment has faded over
+        let (switches, settle) = ([0, 32, 300], conv.capacity() / 32 + 1);
+        let mut current = 0;
+        for (i, block) in signal.chunks_exact(32).enumerate() {
+            if let Some(next) = switches.iter().position(|s| *s == i && i > 0) {
+                let partition = cover(conv.partition(), filters[next].len());
+                assert!(partition.len() <= conv.partition().len());
+                conv.update_filter(
+                    &process_filter(vec![filters[next].clone()], &partition).unwrap(),
+                )
+                .unwrap();
+                current = next;
+            }
+
+            let out = conv.process_block([block].into_iter()).next().unwrap();
+            if current == 0 || i >= switches[current] + settle {
+                for (o, c) in out.iter().zip(&controls[current][i * 32..i * 32 + 32]) {
+                    assert!((o - c).abs() < 1e-9, "{scheduling:?} at block {i}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn capacity_is_checked() {
+    let filter = vec![vec![0.5f32; 1000]];
+
+    assert_eq!(
+        ConvBuilder::new(32)
+            .channels(1)
+            .partition(&[(32, 8), (128, 6)])
+            .max_filter_len(2000)
+            .build(filter.clone())
+            .err(),
+        Some(ConvError::FilterTooLong {
+            len: 2000,
+            capacity: 1024
+        })
+    );
+
+    let mut conv = ConvBuilder::new(32)
+        .channels(1)
+        .partition(&[(32, 8), (128, 6)])
+        .build(filter)
+        .unwrap();
+    let longer = process_filter(vec![vec![0.5f32; 1500]], &[(32, 8), (128, 10)]).unwrap();
+    assert_eq!(
+        conv.update_filter(&longer).err(),
+        Some(ConvError::PartitionMismatch)
+    );
+    let shorter = process_filter(vec![vec![0.5f32; 100]], &cover(conv.partition(), 100)).unwrap();
+    assert_eq!(shorter.partition(), &[(32, 4)]);
+    assert_eq!(conv.update_filter(&shorter), Ok(()));
+}
@@ -0,0 +1,23 @@
+// every test file that uses this builds its own copy, and not all of them use everything
+#![allow(dead_code)]
+
+use convrs::sample::Sample;
+
+/// the convolution done the slow way, one output sample at a time, as long as the signal
+pub fn direct_conv<T: Sample>(signal: &[T], filter: &[T]) -> Vec<T> {
+    (0..signal.len())
+        .map(|n| {
+            filter
+                .iter()
+                .zip(signal[0..=n].iter().rev())
+                .fold(T::zero(), |acc, (f, s)| acc + *f * *s)
+        })
+        .collect()
+}
+
+/// a test filter, a sine at `rate` radians a sample that dies away by e every `decay` samples
+pub fn decaying(len: usize, rate: f64, decay: f64) -> Vec<f64> {
+    (0..len)
+        .map(|i| (i as f64 * rate).sin() * (-(i as f64) / decay).exp())
+        .collect()
+}
@@ -1,7 +1,11 @@
-use std::{thread, time::Duration};
-
-use convrs::{self, conv::Conv, helpers::process_filter};
-use hound::{WavReader, WavSpec, WavWriter};
+use convrs::{
+    self,
+    conv::{Conv, Scheduling},
+    helpers::process_filter,
+    ir::load_wav_file,
+    upconv::{FadeCurve, Routing},
+};
+use hound::{WavSpec, WavWriter};
 use realfft::{num_complex::Complex, RealFftPlanner};
 
 #[test]
@@ -9,17 +13,22 @@ fn correctness() {
     let signal = load_signal();
     let short = load_short();
 
-    let mut control_l = basic_fft_conv(&signal.0, &short[0]);
-    let mut control_r = basic_fft_conv(&signal.1, &short[1]);
+    let control_l = basic_fft_conv(&signal.0, &short[0]);
+    let control_r = basic_fft_conv(&signal.1, &short[1]);
 
-    // NOTE before, i thought we were using partition that worked,
-    // but we got weird click in middle, so for some reason, this is only
-    // good with this partition
-    // TODO probably want to make other tests with different partitions, but
-    // this is good enough for basic correctness
+    // the partitions test checks the gain is right for other partitions
     let partition = &[(128, 22), (1024, 21), (8192, 23)];
-    let short_processed = process_filter(short, partition);
-    let mut conv = Conv::new(128, &short_processed, partition, 2);
+    let short_processed = process_filter(short, partition).unwrap();
+    let mut conv = Conv::new(
+        128,
+        &short_processed,
+        Routing::Parallel(2),
+        128,
+        FadeCurve::default(),
+        // so the background segments are always done in time, however slow the test runs
+        Scheduling::Offline,
+    )
+    .unwrap();
 
     let mut test_l_out = vec![];
     let mut test_r_out = vec![];
@@ -27,8 +36,6 @@ fn correctness() {
     for (l_block, r_block) in signal.0.chunks_exact(128).zip(signal.1.chunks_exact(128)) {
         let vec = [l_block, r_block].concat();
 
-        thread::sleep(Duration::from_millis(3));
-
         let mut out = conv.process_block(vec.chunks_exact(128));
 
         let out_l = out.next().unwrap();
@@ -38,21 +45,23 @@ fn correctness() {
         test_r_out.extend_from_slice(out_r);
     }
 
-    // TODO this is little bit heuristic, see if we can find general way to
-    // convert gain situation, to make sure that the only difference between the two
-    // is the gain, for example use average absolute difference
-    let idx = control_l.len() / 2;
-    let diff = control_l[idx].abs() / test_l_out[idx].abs();
-    for (l, r) in control_l.iter_mut().zip(control_r.iter_mut()) {
-        *l /= diff;
-        *r /= diff;
+    // the output is at unity gain, with no latency from `process_block`,
+    // so it should be the exact convolution, give or take rounding
+    for (test, control) in [(&test_l_out, &control_l), (&test_r_out, &control_r)] {
+        let error = test
+            .iter()
+            .zip(control.iter())
+            .map(|(t, c)| (t - c).abs())
+            .fold(0.0, f32::max);
+        let peak = control.iter().map(|c| c.abs()).fold(0.0, f32::max);
+        assert!(error < peak * 1e-5);
     }
 
     write_to_wav((control_l.as_slice(), control_r.as_slice()), "control.wav");
     write_to_wav((test_l_out.as_slice(), test_r_out.as_slice()), "test.wav");
 }
 
-fn basic_fft_conv<'fft_conv>(signal: &[f32], filter: &[f32]) -> Vec<f32> {
+fn basic_fft_conv(signal: &[f32], filter: &[f32]) -> Vec<f32> {
     let fft_len = signal.len().max(filter.len()) * 2;
 
     let mut planner = RealFftPlanner::<f32>::new();
@@ -81,12 +90,16 @@ fn basic_fft_conv<'fft_conv>(signal: &[f32], filter: &[f32]) -> Vec<f32> {
     out.fill(0.0);
     ifft.process(&mut filter_spectrum, &mut out).unwrap();
 
-    out[0..fft_len / 2].into()
+    // realfft doesn't normalize
+    out[0..fft_len / 2]
+        .iter()
+        .map(|o| o / fft_len as f32)
+        .collect()
 }
 
 fn write_to_wav(data: (&[f32], &[f32]), filename: &str) {
-    let mut root: String = "/Users/andrewthomas/dev/diy/convrs/tests/test_sounds/out/".to_owned();
-    root.push_str(filename);
+    // somewhere cargo keeps for tests, so running them doesn't touch the tree
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(filename);
 
     let spec = WavSpec {
         channels: 2,
@@ -95,7 +108,7 @@ fn write_to_wav(data: (&[f32], &[f32]), filename: &str) {
         sample_format: hound::SampleFormat::Float,
     };
 
-    let mut writer = WavWriter::create(root, spec).unwrap();
+    let mut writer = WavWriter::create(path, spec).unwrap();
 
     for (l, r) in data.0.iter().zip(data.1.iter()) {
         writer.write_sample(*l).unwrap();
@@ -106,38 +119,24 @@ fn write_to_wav(data: (&[f32], &[f32]), filename: &str) {
 }
 
 fn load_signal() -> (Vec<f32>, Vec<f32>) {
-    let mut reader =
-        WavReader::open("/Users/andrewthomas/dev/diy/convrs/tests/test_sounds/in/piano.wav")
-            .unwrap();
-
-    let signal: Vec<f32> = reader
-        .samples::<i16>()
-        .map(|s| s.unwrap() as f32 / i16::MAX as f32)
-        .collect();
-
-    let mut l_out = vec![];
-    let mut r_out = vec![];
-
-    for (s, i) in signal.iter().zip(0..) {
-        if i % 2 == 0 {
-            l_out.push(*s);
-        } else {
-            r_out.push(*s);
-        }
-    }
-
-    (l_out, r_out)
+    let mut signal = load_wav_file(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/test_sounds/in/piano.wav"
+    ))
+    .unwrap()
+    .channels;
+
+    let r = signal.pop().unwrap();
+    (signal.pop().unwrap(), r)
 }
 
 fn load_short() -> Vec<Vec<f32>> {
-    let mut reader =
-        WavReader::open("/Users/andrewthomas/dev/diy/convrs/tests/test_sounds/IRs/short2.wav")
-            .unwrap();
-
-    let filter: Vec<f32> = reader
-        .samples::<i32>()
-        .map(|s| s.unwrap() as f32 / i32::MAX as f32)
-        .collect();
-
-    vec![filter.clone(), filter.clone()]
+    load_wav_file(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/test_sounds/IRs/short2.wav"
+    ))
+    .unwrap()
+    .map_channels(Routing::Parallel(2))
+    .unwrap()
+    .channels
 }
@@ -0,0 +1,99 @@
+use convrs::{
+    conv::{ConvBuilder, ConvError, Scheduling},
+    helpers::process_filter_padded,
+    upconv::{FadeCurve, Overlap, Scheme},
+};
+
+mod common;
+use common::{decaying, direct_conv};
+
+#[test]
+fn every_segment_fades_at_the_same_point() {
+    let partition = [(32, 8), (128, 6), (512, 4)];
+    let filters = [
+        decaying(3000, 0.11, 900.0),
+        decaying(2800, 0.05, 900.0),
+        decaying(1500, 0.31, 900.0),
+    ];
+    let signal: Vec<f64> = (0..6000)
+        .map(|i| (i as f64 * 0.029).sin() + ((i * 5) % 11) as f64 / 11.0)
+        .collect();
+    let controls: Vec<Vec<f64>> = filters.iter().map(|f| direct_conv(&signal, f)).collect();
+
+    for overlap in [Overlap::Save, Overlap::Add] {
+        for curve in [
+            FadeCurve::Linear,
+            FadeCurve::RaisedCosine,
+            FadeCurve::EqualPower,
+        ] {
+            for scheduling in [Scheduling::Offline, Scheduling::Distributed] {
+                let scheme = Scheme {
+                    overlap,
+                    fft_ratio: 2,
+                };
+                let mut conv = ConvBuilder::new(32)
+                    .channels(1)
+                    .partition(&partition)
+                    .schemes(&[scheme; 3])
+                    .scheduling(scheduling)
+                    .fade_curve(curve)
+                    .fade_len(512)
+                    .build(vec![filters[0].clone()])
+                    .unwrap();
+
+                // the output sample each fade starts at, and the filters it fades between
+                let mut fades: Vec<(usize, usize, usize)> = vec![];
+                // the whole output is the ideal crossfade between the direct convolutions
+                let expected = |fades: &[(usize, usize, usize)], n: usize| match fades
+                    .iter()
+                    .rev()
+                    .find(|f| f.0 <= n)
+                {
+                    Some((start, from, to)) => {
+                        let (old_gain, new_gain) =
+                            curve.gains(((n - start) as f64 / 512.0).min(1.0));
+                        controls[*from][n] * old_gain + controls[*to][n] * new_gain
+                    }
+                    None => controls[0][n],
+                };
+
+                for (i, block) in signal.chunks_exact(32).enumerate() {
+                    // the second update is tried every block from the one after the first,
+                    // and has to wait until the first has faded all the way in
+                    let next = match fades.len() {
+                        0 if i == 40 => Some(1),
+                        1 => Some(2),
+                        _ => None,
+                    };
+                    if let Some(next) = next {
+                        let processed =
+                            process_filter_padded(vec![filters[next].clone()], &partition, &[2; 3])
+                                .unwrap();
+                        let start = i * 32 + conv.fade_delay();
+                        match conv.update_filter(&processed) {
+                            Ok(()) => {
+                                let from = fades.last().map_or(0, |f| f.2);
+                                fades.push((start, from, next));
+                            }
+                            Err(e) => {
+                                assert_eq!(e, ConvError::FadePending);
+                                assert!(i * 32 < fades[0].0 + 512);
+                            }
+                        }
+                    }
+
+                    let out = conv.process_block([block].into_iter()).next().unwrap();
+                    for (j, o) in out.iter().enumerate() {
+                        let e = expected(&fades, i * 32 + j);
+                        assert!(
+                            (o - e).abs() < 1e-9,
+                            "{overlap:?} {curve:?} {scheduling:?} at sample {}",
+                            i * 32 + j
+                        );
+                    }
+                }
+                assert_eq!(fades.len(), 2);
+            }
+        }
+    }
+}
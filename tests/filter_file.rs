@@ -0,0 +1,64 @@
+use convrs::{
+    filter::{FilterFileError, ProcessedFilter},
+    helpers::{process_filter, process_filter_padded},
+};
+
+#[test]
+fn filters_round_trip() {
+    let filter: Vec<Vec<f32>> = (0..2)
+        .map(|c| {
+            (0..700)
+                .map(|i| ((i * (c + 3)) as f32 * 0.1).sin())
+                .collect()
+        })
+        .collect();
+    let processed = process_filter(filter, &[(64, 4), (128, 4)])
+        .unwrap()
+        .with_sample_rate(48000);
+
+    let mut bytes = vec![];
+    processed.save(&mut bytes).unwrap();
+
+    let loaded = ProcessedFilter::<f32>::load(&bytes[..]).unwrap();
+    assert_eq!(loaded, processed);
+    assert_eq!(loaded.sample_rate(), Some(48000));
+    assert_eq!(loaded.source_len(), 700);
+
+    // a file saved at one precision can be loaded at the other
+    let wide = ProcessedFilter::<f64>::load(&bytes[..]).unwrap();
+    assert_eq!(wide.partition(), processed.partition());
+    for (w, p) in wide.data().iter().zip(processed.data()) {
+        assert_eq!(w.re as f32, p.re);
+        assert_eq!(w.im as f32, p.im);
+    }
+
+    // padded filters keep their fft sizes
+    let padded =
+        process_filter_padded(vec![vec![0.25f32; 300]], &[(64, 2), (128, 2)], &[4, 3]).unwrap();
+    let mut padded_bytes = vec![];
+    padded.save(&mut padded_bytes).unwrap();
+    let loaded = ProcessedFilter::<f32>::load(&padded_bytes[..]).unwrap();
+    assert_eq!(loaded, padded);
+    assert_eq!(loaded.fft_ratios(), &[4, 3]);
+
+    let mut corrupt = bytes.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        ProcessedFilter::<f32>::load(&corrupt[..]),
+        Err(FilterFileError::ChecksumMismatch)
+    ));
+    assert!(matches!(
+        ProcessedFilter::<f32>::load(&bytes[0..bytes.len() - 8]),
+        Err(FilterFileError::ChecksumMismatch)
+    ));
+    let mut newer = bytes.clone();
+    newer[4..8].copy_from_slice(&2u32.to_le_bytes());
+    assert!(matches!(
+        ProcessedFilter::<f32>::load(&newer[..]),
+        Err(FilterFileError::UnsupportedVersion(2))
+    ));
+    assert!(matches!(
+        ProcessedFilter::<f32>::load(&b"RIFF0000"[..]),
+        Err(FilterFileError::NotAFilter)
+    ));
+}
@@ -0,0 +1,67 @@
+use convrs::{
+    conv::ConvBuilder,
+    helpers::{deinterleave, process_filter, process_filter_interleaved},
+};
+
+#[test]
+fn entry_points_agree() {
+    let filter: Vec<f32> = (0..600)
+        .map(|i| (i as f32 * 0.29).sin() * (-(i as f32) / 150.0).exp())
+        .collect();
+    let partition = &[(32, 19)];
+    let processed = process_filter_interleaved(&filter, 2, partition).unwrap();
+    assert_eq!(
+        processed,
+        process_filter(deinterleave(&filter, 2), partition).unwrap()
+    );
+
+    let signal: Vec<f32> = (0..4000).map(|i| (i as f32 * 0.07).sin()).collect();
+    let builder = ConvBuilder::new(32).max_block_size(50);
+    let mut by_channel = builder.build_processed(&processed).unwrap();
+    let mut interleaved = builder.build_processed(&processed).unwrap();
+    let mut in_place = builder.build_processed(&processed).unwrap();
+
+    let mut out = vec![0.0; 100];
+    for frames in signal.chunks(100) {
+        let mut channels = deinterleave(frames, 2);
+        let (mut r, mut l) = (channels.pop().unwrap(), channels.pop().unwrap());
+
+        let expected: Vec<Vec<f32>> = by_channel
+            .process([&l[..], &r[..]].into_iter())
+            .map(Vec::from)
+            .collect();
+
+        interleaved.process_interleaved(frames, &mut out);
+        assert_eq!(deinterleave(&out[0..frames.len()], 2), expected);
+
+        in_place.process_in_place(&mut [&mut l, &mut r]);
+        assert_eq!(vec![l, r], expected);
+    }
+}
+
+#[test]
+fn buffers_longer_than_the_max_block_are_chunked() {
+    let filter = vec![(0..400)
+        .map(|i| (i as f32 * 0.41).cos() * (-(i as f32) / 90.0).exp())
+        .collect::<Vec<f32>>()];
+    let signal: Vec<f32> = (0..3000).map(|i| (i as f32 * 0.05).sin()).collect();
+    let builder = ConvBuilder::new(32).channels(1).max_block_size(50);
+    let mut short = builder.build(filter.clone()).unwrap();
+    let mut interleaved = builder.build(filter.clone()).unwrap();
+    let mut in_place = builder.build(filter).unwrap();
+
+    let mut out = vec![0.0; 130];
+    for buffer in signal.chunks(130) {
+        let mut expected = vec![];
+        for block in buffer.chunks(50) {
+            expected.extend_from_slice(short.process([block].into_iter()).next().unwrap());
+        }
+
+        interleaved.process_interleaved(buffer, &mut out);
+        assert_eq!(&out[0..buffer.len()], &expected[..]);
+
+        let mut buffer = buffer.to_vec();
+        in_place.process_in_place(&mut [&mut buffer]);
+        assert_eq!(buffer, expected);
+    }
+}
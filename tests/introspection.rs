@@ -0,0 +1,79 @@
+use convrs::{
+    conv::{Conv, ConvBuilder, Scheduling},
+    helpers::{process_filter, split_head},
+    partition::cover,
+};
+
+#[test]
+fn latency_and_tail_match_the_output() {
+    let filter: Vec<f32> = (0..1000).map(|i| 1.0 - i as f32 / 1000.0).collect();
+    let mut impulse = vec![0.0f32; 4096];
+    impulse[0] = 1.0;
+
+    let plain = ConvBuilder::new(32)
+        .channels(1)
+        .partition(&[(32, 32)])
+        .build(vec![filter.clone()])
+        .unwrap();
+
+    let (head, tail) = split_head(vec![filter.clone()], 64, 32).unwrap();
+    let mut with_head = ConvBuilder::new(32)
+        .channels(1)
+        .partition(&[(32, 32)])
+        .build(tail)
+        .unwrap();
+    with_head.set_head(&head).unwrap();
+
+    assert!(plain.memory_usage() > filter.len() * std::mem::size_of::<f32>());
+
+    for mut conv in [plain, with_head] {
+        let out: Vec<f32> = impulse
+            .chunks(32)
+            .flat_map(|b| conv.process([b].into_iter()).next().unwrap().to_vec())
+            .collect();
+
+        let first = out.iter().position(|s| s.abs() > 1e-4).unwrap();
+        let last = out.iter().rposition(|s| s.abs() > 1e-4).unwrap();
+        assert_eq!(first, conv.latency());
+        assert_eq!(last + 1, conv.tail_len());
+    }
+}
+
+/// swaps in `filter`, processed for as much of the partition as it needs
+fn update(conv: &mut Conv, filter: &[f32]) {
+    let partition = cover(conv.partition(), filter.len());
+    conv.update_filter(&process_filter(vec![filter.to_vec()], &partition).unwrap())
+        .unwrap();
+}
+
+#[test]
+fn tail_follows_filter_updates() {
+    let (short, long) = (vec![0.5f32; 300], vec![0.5f32; 2800]);
+    let mut conv = ConvBuilder::new(32)
+        .channels(1)
+        .max_filter_len(2800)
+        .scheduling(Scheduling::Offline)
+        .build(vec![short.clone()])
+        .unwrap();
+    assert_eq!(conv.tail_len(), 32 + 300);
+
+    let silence = [0.0f32; 32];
+    // the fade ends one block after it starts, with no fade length set
+    let mut fade_blocks = conv.fade_delay() / 32 + 1;
+    update(&mut conv, &long);
+    assert_eq!(conv.tail_len(), 32 + 2800);
+
+    for _ in 0..fade_blocks {
+        let _ = conv.process_block([&silence[..]].into_iter());
+    }
+    let fade_delay = conv.fade_delay();
+    fade_blocks = fade_delay / 32 + 1;
+    update(&mut conv, &short);
+    // the long filter is faded out, so it's heard until the fade's over
+    assert_eq!(conv.tail_len(), 32 + (fade_delay + 32).min(2800));
+
+    for _ in 0..fade_blocks {
+        let _ = conv.process_block([&silence[..]].into_iter());
+    }
+    assert_eq!(conv.tail_len(), 32 + 300);
+}
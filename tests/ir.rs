@@ -0,0 +1,127 @@
+use std::io::Cursor;
+
+use convrs::{
+    ir::{load_wav, load_wav_file, IrError},
+    upconv::{FadeCurve, Routing},
+};
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+#[test]
+fn wavs_load_at_full_scale() {
+    for (format, bits) in [
+        (SampleFormat::Int, 16),
+        (SampleFormat::Int, 24),
+        (SampleFormat::Int, 32),
+        (SampleFormat::Float, 32),
+    ] {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: bits,
+            sample_format: format,
+        };
+        let mut bytes = Cursor::new(vec![]);
+        let mut writer = WavWriter::new(&mut bytes, spec).unwrap();
+        let half = 1i64 << (bits - 2);
+        for _ in 0..10 {
+            match format {
+                SampleFormat::Int => {
+                    writer.write_sample(half as i32).unwrap();
+                    writer.write_sample(-half as i32).unwrap();
+                }
+                SampleFormat::Float => {
+                    writer.write_sample(0.5f32).unwrap();
+                    writer.write_sample(-0.5f32).unwrap();
+                }
+            }
+        }
+        writer.finalize().unwrap();
+
+        let ir = load_wav::<f32>(&bytes.get_ref()[..]).unwrap();
+        assert_eq!(ir.sample_rate, 44100);
+        assert_eq!(ir.channels, vec![vec![0.5; 10], vec![-0.5; 10]]);
+
+        let mono = ir.map_channels(Routing::Parallel(1)).unwrap();
+        assert_eq!(mono.channels, vec![vec![0.0; 10]]);
+    }
+}
+
+#[test]
+fn irs_are_mapped_and_resampled() {
+    let ir = load_wav_file::<f32>(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/test_sounds/IRs/long_stereo2.wav"
+    ))
+    .unwrap();
+    assert_eq!(ir.channels.len(), 2);
+
+    assert!(matches!(
+        ir.clone().map_channels(Routing::Parallel(3)),
+        Err(IrError::ChannelMismatch { ir: 2, .. })
+    ));
+    let true_stereo = ir
+        .clone()
+        .map_channels(Routing::Matrix {
+            inputs: 2,
+            outputs: 2,
+        })
+        .unwrap();
+    assert_eq!(true_stereo.channels[0], ir.channels[0]);
+    assert!(true_stereo.channels[1].is_empty());
+    assert!(true_stereo.channels[2].is_empty());
+    assert_eq!(true_stereo.channels[3], ir.channels[1]);
+
+    // a sine should come out as the same sine at the new rate
+    let sine = convrs::ir::ImpulseResponse {
+        channels: vec![(0..4800)
+            .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 48000.0).sin())
+            .collect()],
+        sample_rate: 48000,
+    };
+    let resampled = sine.resample(44100).unwrap();
+    assert_eq!(resampled.sample_rate, 44100);
+    assert_eq!(resampled.len(), 4410);
+    // the ends are smeared by the resampler's filter
+    for (i, s) in resampled.channels[0]
+        .iter()
+        .enumerate()
+        .skip(200)
+        .take(4000)
+    {
+        let expected = (i as f32 * 440.0 * std::f32::consts::TAU / 44100.0).sin();
+        assert!((s - expected).abs() < 1e-2);
+    }
+}
+
+#[test]
+fn edits_compose() {
+    let ir = convrs::ir::ImpulseResponse {
+        channels: vec![
+            vec![0.0, 0.001, 0.0, 1.0, -0.5, 0.25, 0.001, 0.0],
+            vec![0.0, 0.0, 0.5, 0.0, 0.0],
+        ],
+        sample_rate: 48000,
+    };
+
+    let trimmed = ir.clone().trim_start(0.01).trim_end(0.01);
+    assert_eq!(
+        trimmed.channels,
+        vec![vec![0.0, 1.0, -0.5, 0.25], vec![0.5, 0.0, 0.0]]
+    );
+
+    let delayed = trimmed.clone().predelay(2).reverse();
+    assert_eq!(
+        delayed.channels,
+        vec![
+            vec![0.25, -0.5, 1.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.5, 0.0, 0.0]
+        ]
+    );
+
+    let faded = trimmed.clone().fade_out(2, FadeCurve::Linear);
+    assert_eq!(faded.channels[0], vec![0.0, 1.0, -0.5, 0.125]);
+
+    let normalized = trimmed.normalize(1.0);
+    let energy: f32 = normalized.channels.iter().flatten().map(|s| s * s).sum();
+    assert!((energy / 2.0 - 1.0).abs() < 1e-6);
+}
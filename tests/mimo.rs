@@ -0,0 +1,79 @@
+use convrs::{
+    conv::{Conv, Scheduling},
+    helpers::{process_filter, split_head},
+    upconv::{FadeCurve, Routing},
+};
+
+mod common;
+use common::direct_conv;
+
+#[test]
+fn true_stereo_sums_every_path() {
+    let routing = Routing::Matrix {
+        inputs: 2,
+        outputs: 2,
+    };
+    // LL, RL, LR, RR
+    let filters: Vec<Vec<f32>> = (0..routing.paths())
+        .map(|p| {
+            (0..300)
+                .map(|i| (i as f32 * (0.2 + p as f32 * 0.13)).sin() * (-(i as f32) / 80.0).exp())
+                .collect()
+        })
+        .collect();
+    let signals: Vec<Vec<f32>> = (0..2)
+        .map(|c| {
+            (0..2000)
+                .map(|i| (i as f32 * (0.05 + c as f32 * 0.07)).sin())
+                .collect()
+        })
+        .collect();
+
+    let mut control = vec![vec![0.0; 2000]; 2];
+    for (p, filter) in filters.iter().enumerate() {
+        let (input, output) = routing.path(p);
+        for (c, d) in control[output]
+            .iter_mut()
+            .zip(direct_conv(&signals[input], filter))
+        {
+            *c += d;
+        }
+    }
+
+    for head_len in [None, Some(48)] {
+        let (head, tail) = match head_len {
+            Some(head_len) => {
+                let (head, tail) = split_head(filters.clone(), head_len, 32).unwrap();
+                (Some(head), tail)
+            }
+            None => (None, filters.clone()),
+        };
+        let partition = &[(32, tail[0].len().div_ceil(32))];
+
+        let mut conv = Conv::new(
+            32,
+            &process_filter(tail, partition).unwrap(),
+            routing,
+            32,
+            FadeCurve::default(),
+            Scheduling::default(),
+        )
+        .unwrap();
+        if let Some(head) = head {
+            conv.set_head(&head).unwrap();
+        }
+
+        let mut test = vec![vec![]; 2];
+        for (l, r) in signals[0].chunks_exact(32).zip(signals[1].chunks_exact(32)) {
+            for (t, o) in test.iter_mut().zip(conv.process_block([l, r].into_iter())) {
+                t.extend_from_slice(o);
+            }
+        }
+
+        for (test, control) in test.iter().zip(&control) {
+            for (t, c) in test.iter().zip(control) {
+                assert!((t - c).abs() < 1e-3);
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use convrs::{
+    conv::{ConvBuilder, MissPolicy, Scheduling},
+    upconv::FadeCurve,
+};
+
+#[test]
+fn missed_blocks_follow_the_policy() {
+    // the background segment's blocks are due one 32 sample block after they're sent,
+    // and they're all done in time offline, so the misses are the ones forced below
+    let partition = [(32, 9), (256, 2)];
+    let mut filter = vec![0.0f32; 32 * 9 + 1];
+    // the only tap is the first of the background segment, so everything that comes
+    // out is that segment's, and with a constant input every block it sends back is ones
+    filter[32 * 9] = 1.0;
+    let (avail, offset) = (8, 9);
+    // which of the segment's blocks miss, so there are misses with a block held,
+    // and one straight after another
+    let forced = [1, 3, 4];
+
+    for policy in [MissPolicy::Silence, MissPolicy::Hold, MissPolicy::Fade] {
+        let mut conv = ConvBuilder::new(32)
+            .channels(1)
+            .partition(&partition)
+            .scheduling(Scheduling::Offline)
+            .miss_policy(policy)
+            .fade_curve(FadeCurve::Linear)
+            .build(vec![filter.clone()])
+            .unwrap();
+        let monitor = conv.deadline_monitor();
+        let input = [1.0f32; 32];
+
+        // what the segment is playing, from the cycle it was due, and whether
+        // there's a block held for the next miss
+        let mut span = (0, vec![0.0f32; 256]);
+        let mut held = false;
+
+        for cycle in 1..offset + avail * 7 {
+            let due = cycle >= offset && (cycle - offset) % avail == 0;
+            let missed = due && forced.contains(&((cycle - offset) / avail));
+            if missed {
+                conv.force_miss(1);
+            }
+
+            let out = conv.process_block([&input[..]].into_iter()).next().unwrap();
+
+            // a block due at cycle d comes out over the cycles after it, starting
+            // with the one after d, so this is checked before any new block is due
+            if span.0 > 0 {
+                let k = cycle - span.0 - 1;
+                for (o, v) in out.iter().zip(&span.1[32 * k..32 * k + 32]) {
+                    assert!((o - v).abs() < 1e-5, "{policy:?} at cycle {cycle}");
+                }
+            } else {
+                assert!(out.iter().all(|o| o.abs() < 1e-5));
+            }
+
+            if due {
+                let mut values = vec![0.0f32; 256];
+                match (missed, policy) {
+                    (false, _) => {
+                        values.fill(1.0);
+                        held = true;
+                    }
+                    (true, MissPolicy::Silence) => held = false,
+                    (true, MissPolicy::Hold) => values.fill(if held { 1.0 } else { 0.0 }),
+                    (true, MissPolicy::Fade) => {
+                        if held {
+                            for (j, v) in values.iter_mut().enumerate() {
+                                *v = FadeCurve::Linear.gains(j as f32 / 256.0).0;
+                            }
+                        }
+                        held = false;
+                    }
+                }
+                span = (cycle, values);
+            }
+        }
+
+        assert_eq!(monitor.segments(), 2);
+        assert_eq!(monitor.missed(0), 0);
+        assert_eq!(monitor.missed(1), forced.len());
+        assert_eq!(monitor.total(), forced.len());
+    }
+}
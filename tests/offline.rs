@@ -0,0 +1,49 @@
+use convrs::conv::{ConvBuilder, Scheduling};
+use std::{thread, time::Duration};
+
+#[test]
+fn offline_matches_threaded() {
+    let filter: Vec<Vec<f32>> = (0..2)
+        .map(|c| {
+            (0..5000)
+                .map(|i| ((i * (c + 2)) as f32 * 0.05).sin() * (-(i as f32) / 2000.0).exp())
+                .collect()
+        })
+        .collect();
+    let signal: Vec<f32> = (0..20000).map(|i| (i as f32 * 0.031).sin()).collect();
+
+    let builder = ConvBuilder::new(64).partition(&[(64, 8), (256, 8), (1024, 3)]);
+    let run = |scheduling, pause| {
+        let mut conv = builder
+            .clone()
+            .scheduling(scheduling)
+            .build(filter.clone())
+            .unwrap();
+        let out: Vec<Vec<f32>> = signal
+            .chunks_exact(64)
+            .flat_map(|block| {
+                // gives the workers time to get their blocks back
+                thread::sleep(pause);
+                conv.process_block([block, block].into_iter())
+                    .map(Vec::from)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        (out, conv.deadline_monitor().total())
+    };
+
+    let (offline, misses) = run(Scheduling::Offline, Duration::ZERO);
+    assert_eq!(misses, 0);
+
+    // a busy machine can still make a worker miss a block, which changes what comes
+    // out, so the threaded run is tried again until it gets every block back in time
+    for _ in 0..10 {
+        let (threaded, misses) = run(Scheduling::Threaded, Duration::from_micros(200));
+        if misses == 0 {
+            assert!(threaded == offline, "offline and threaded outputs differ");
+            return;
+        }
+    }
+    panic!("the workers never got every block back in time");
+}
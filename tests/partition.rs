@@ -0,0 +1,129 @@
+use convrs::{
+    conv::{ConvBuilder, ConvError, Scheduling},
+    partition::{cost, plan, validate, Constraints},
+};
+
+mod common;
+use common::direct_conv;
+
+#[test]
+fn planned_partitions_are_schedulable() {
+    let constraints = Constraints::default();
+
+    for block_size in [32, 64, 128, 512] {
+        for ir_len in [0, 1, 100, 2816, 48000, 96000, 212736, 480000] {
+            let partition = plan(ir_len, block_size, 2, &constraints).unwrap();
+
+            assert_eq!(validate(&partition, block_size), Ok(()));
+
+            let mut offset = 0;
+            for (i, (size, num_blocks)) in partition.iter().enumerate() {
+                assert!(*size <= constraints.max_block_size.max(block_size));
+                if i > 0 {
+                    assert!(offset >= size * (constraints.headroom + 1));
+                }
+
+                offset += size * num_blocks;
+            }
+
+            assert!(offset >= ir_len);
+        }
+    }
+}
+
+#[test]
+fn planned_partitions_beat_uniform() {
+    let constraints = Constraints::default();
+
+    let partition = plan(212736, 128, 2, &constraints).unwrap();
+    let uniform = [(128, 212736 / 128)];
+
+    assert!(partition.len() > 1);
+    assert!(cost(&partition, 2, &constraints) < cost(&uniform, 2, &constraints));
+}
+
+#[test]
+fn invalid_partitions_are_rejected() {
+    assert_eq!(validate(&[], 128), Err(ConvError::EmptyPartition));
+    assert_eq!(
+        validate(&[(256, 4)], 128),
+        Err(ConvError::BlockSizeMismatch {
+            block_size: 128,
+            first_segment: 256
+        })
+    );
+    assert_eq!(
+        validate(&[(128, 22), (1024, 0)], 128),
+        Err(ConvError::EmptySegment { segment: 1 })
+    );
+    assert_eq!(
+        validate(&[(128, 22), (384, 21)], 128),
+        Err(ConvError::InvalidSegmentSize {
+            segment: 1,
+            size: 384
+        })
+    );
+    assert_eq!(
+        validate(&[(128, 22), (1024, 21), (512, 4)], 128),
+        Err(ConvError::InvalidSegmentSize {
+            segment: 2,
+            size: 512
+        })
+    );
+    assert_eq!(
+        validate(&[(128, 8), (1024, 21)], 128),
+        Err(ConvError::SegmentTooEarly {
+            segment: 1,
+            offset: 1024,
+            size: 1024
+        })
+    );
+    assert_eq!(validate(&[(128, 22), (1024, 21), (8192, 23)], 128), Ok(()));
+
+    assert_eq!(
+        plan(1000, 0, 2, &Constraints::default()),
+        Err(ConvError::ZeroBlockSize)
+    );
+    assert_eq!(
+        plan(
+            1000,
+            128,
+            2,
+            &Constraints {
+                headroom: 0,
+                ..Constraints::default()
+            }
+        ),
+        Err(ConvError::NoHeadroom)
+    );
+}
+
+#[test]
+fn every_partition_has_unity_gain() {
+    let filter: Vec<f64> = (0..3000)
+        .map(|i| (i as f64 * 0.21).sin() * (-(i as f64) / 800.0).exp())
+        .collect();
+    let signal: Vec<f64> = (0..8192).map(|i| (i as f64 * 0.017).sin()).collect();
+    let control = direct_conv(&signal, &filter);
+
+    for partition in [
+        vec![(32, 94)],
+        vec![(32, 8), (128, 22)],
+        vec![(32, 8), (128, 6), (512, 5)],
+        plan(filter.len(), 32, 1, &Constraints::default()).unwrap(),
+    ] {
+        let mut conv = ConvBuilder::new(32)
+            .channels(1)
+            .partition(&partition)
+            .scheduling(Scheduling::Offline)
+            .build(vec![filter.clone()])
+            .unwrap();
+
+        for (block, control) in signal.chunks_exact(32).zip(control.chunks_exact(32)) {
+            let out = conv.process_block([block].into_iter()).next().unwrap();
+            for (o, c) in out.iter().zip(control) {
+                assert!((o - c).abs() < 1e-9, "{partition:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use convrs::{
+    conv::{ConvBuilder, Scheduling},
+    helpers::split_head,
+};
+
+#[test]
+fn reset_clears_the_tail() {
+    let filter: Vec<f32> = (0..3000)
+        .map(|i| (i as f32 * 0.13).sin() * (-(i as f32) / 1000.0).exp())
+        .collect();
+    let noise: Vec<f32> = (0..2048)
+        .map(|i| ((i * 7919) % 113) as f32 / 56.0 - 1.0)
+        .collect();
+    let impulse: Vec<f32> = (0..4096).map(|i| if i == 3 { 1.0 } else { 0.0 }).collect();
+
+    for scheduling in [Scheduling::Distributed, Scheduling::Threaded] {
+        let builder = ConvBuilder::new(32)
+            .channels(1)
+            .partition(&[(32, 8), (128, 8), (512, 4)])
+            .max_block_size(50)
+            .scheduling(scheduling);
+        let (head, tail) = split_head(vec![filter.clone()], 64, 32).unwrap();
+
+        let mut fresh = builder.build(tail.clone()).unwrap();
+        fresh.set_head(&head).unwrap();
+        let mut conv = builder.build(tail).unwrap();
+        conv.set_head(&head).unwrap();
+
+        // stop part way through a block, so the fifo has something in it too
+        for block in noise.chunks(50) {
+            let _ = conv.process([block].into_iter());
+        }
+        conv.reset();
+
+        // nothing from before the reset comes out, even from the workers
+        for block in vec![0.0; 8192].chunks(32) {
+            let out = conv.process([block].into_iter()).next().unwrap();
+            assert!(out.iter().all(|s| *s == 0.0));
+        }
+
+        // and from then on it's the same as a new one, the workers
+        // might miss blocks, but the audio thread can't
+        if scheduling == Scheduling::Distributed {
+            conv.reset();
+            for block in impulse.chunks(32) {
+                let out = conv.process([block].into_iter()).next().unwrap().to_vec();
+                let expected = fresh.process([block].into_iter()).next().unwrap();
+                assert_eq!(out, expected);
+            }
+        }
+    }
+}
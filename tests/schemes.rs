@@ -0,0 +1,164 @@
+use convrs::{
+    conv::{ConvBuilder, ConvError, Scheduling},
+    helpers::process_filter_padded,
+    upconv::{Overlap, Scheme},
+};
+
+mod common;
+use common::direct_conv;
+
+#[test]
+fn every_scheme_matches_direct_convolution() {
+    let filter: Vec<f64> = (0..2500)
+        .map(|i| (i as f64 * 0.13).cos() * (-(i as f64) / 700.0).exp())
+        .collect();
+    let new_filter: Vec<f64> = filter.iter().map(|f| f * -0.5).collect();
+    let signal: Vec<f64> = (0..8192)
+        .map(|i| (i as f64 * 0.023).sin() + ((i * 7) % 13) as f64 / 13.0)
+        .collect();
+    let control = direct_conv(&signal, &filter);
+    let new_control = direct_conv(&signal, &new_filter);
+
+    let save = |fft_ratio| Scheme {
+        overlap: Overlap::Save,
+        fft_ratio,
+    };
+    let add = |fft_ratio| Scheme {
+        overlap: Overlap::Add,
+        fft_ratio,
+    };
+
+    for schemes in [
+        [save(2), save(2), save(2)],
+        [add(2), add(2), add(2)],
+        [save(4), save(3), save(8)],
+        [add(4), add(3), add(8)],
+        [add(2), save(4), add(3)],
+    ] {
+        let mut conv = ConvBuilder::new(32)
+            .channels(1)
+            .partition(&[(32, 8), (128, 6), (512, 4)])
+            .schemes(&schemes)
+            .scheduling(Scheduling::Offline)
+            .fade_len(32)
+            .build(vec![filter.clone()])
+            .unwrap();
+
+        let blocks = signal.chunks_exact(32).zip(control.chunks_exact(32));
+        for (i, (block, control)) in blocks.enumerate() {
+            // swap the filter half way through, the output should be back to the exact
+            // convolution with the new filter once it's faded in and the old tail is gone
+            let (control, settled) = if i < 128 {
+                (control, true)
+            } else {
+                (&new_control[i * 32..i * 32 + 32], i >= 128 + 3000 / 32)
+            };
+            if i == 128 {
+                let processed = process_filter_padded(
+                    vec![new_filter.clone()],
+                    &[(32, 8), (128, 6), (512, 4)],
+                    &schemes.map(|s| s.fft_ratio),
+                )
+                .unwrap();
+                conv.update_filter(&processed).unwrap();
+            }
+
+            let out = conv.process_block([block].into_iter()).next().unwrap();
+            if settled {
+                for (o, c) in out.iter().zip(control) {
+                    assert!((o - c).abs() < 1e-9, "{schemes:?} at block {i}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn odd_fft_sizes_work() {
+    // 441 * 3 isn't a power of 2, so the ffts need scratch space
+    let filter: Vec<f64> = (0..1500)
+        .map(|i| (i as f64 * 0.07).sin() * (-(i as f64) / 400.0).exp())
+        .collect();
+    let signal: Vec<f64> = (0..441 * 12)
+        .map(|i| (i as f64 * 0.031).cos() + ((i * 3) % 7) as f64 / 7.0)
+        .collect();
+    let control = direct_conv(&signal, &filter);
+
+    for overlap in [Overlap::Save, Overlap::Add] {
+        let mut conv = ConvBuilder::new(441)
+            .channels(1)
+            .partition(&[(441, 4)])
+            .schemes(&[Scheme {
+                overlap,
+                fft_ratio: 3,
+            }])
+            .scheduling(Scheduling::Offline)
+            .build(vec![filter.clone()])
+            .unwrap();
+
+        let blocks = signal.chunks_exact(441).zip(control.chunks_exact(441));
+        for (i, (block, control)) in blocks.enumerate() {
+            let out = conv.process_block([block].into_iter()).next().unwrap();
+            for (o, c) in out.iter().zip(control) {
+                assert!((o - c).abs() < 1e-9, "{overlap:?} at block {i}");
+            }
+        }
+    }
+}
+
+#[test]
+fn schemes_are_checked() {
+    let filter = vec![vec![0.5f32; 1000]];
+    let builder = ConvBuilder::new(32)
+        .channels(1)
+        .partition(&[(32, 8), (128, 6)]);
+
+    assert_eq!(
+        builder
+            .clone()
+            .schemes(&[Scheme::default()])
+            .build(filter.clone())
+            .err(),
+        Some(ConvError::SchemeCount {
+            expected: 2,
+            actual: 1
+        })
+    );
+    let tight = Scheme {
+        overlap: Overlap::Add,
+        fft_ratio: 1,
+    };
+    assert_eq!(
+        builder
+            .clone()
+            .schemes(&[Scheme::default(), tight])
+            .build(filter.clone())
+            .err(),
+        Some(ConvError::InvalidFftRatio {
+            segment: 1,
+            ratio: 1
+        })
+    );
+
+    // the planner decides how many segments there are, and the schemes are checked after
+    let planned = ConvBuilder::new(32)
+        .channels(1)
+        .schemes(&[Scheme::default()])
+        .build(vec![vec![0.5f32; 20000]])
+        .err();
+    assert!(matches!(
+        planned,
+        Some(ConvError::SchemeCount { actual: 1, .. })
+    ));
+
+    // a padded filter needs schemes with the same fft sizes, or none at all
+    let padded = process_filter_padded(filter, &[(32, 8), (128, 6)], &[2, 4]).unwrap();
+    assert!(builder.build_processed(&padded).is_ok());
+    assert_eq!(
+        builder
+            .schemes(&[Scheme::default(), Scheme::default()])
+            .build_processed(&padded)
+            .err(),
+        Some(ConvError::PartitionMismatch)
+    );
+}
@@ -0,0 +1,46 @@
+use convrs::{
+    sample::Sample,
+    simd::{self, Kernel},
+};
+use realfft::num_complex::Complex;
+
+fn spectrum<T: Sample>(len: usize, seed: usize) -> Vec<Complex<T>> {
+    (0..len)
+        .map(|i| {
+            let x = T::from_usize(i * 31 + seed).unwrap();
+            Complex {
+                re: (x * T::from_f64(0.37).unwrap()).sin(),
+                im: (x * T::from_f64(0.91).unwrap()).cos(),
+            }
+        })
+        .collect()
+}
+
+fn check<T: Sample>(tolerance: T) {
+    // the odd lengths leave a tail for the scalar loop
+    for len in [0, 1, 3, 7, 8, 17, 129, 1025] {
+        let a = spectrum::<T>(len, 1);
+        let b = spectrum::<T>(len, 2);
+
+        let mut expected = spectrum::<T>(len, 3);
+        simd::scalar(&mut expected, &a, &b);
+
+        // kernels this cpu can't run fall back to the scalar loop
+        for kernel in Kernel::ALL {
+            let mut accum = spectrum::<T>(len, 3);
+            T::mac(kernel, &mut accum, &a, &b);
+
+            for (x, e) in accum.iter().zip(&expected) {
+                assert!((x - e).norm() < tolerance, "{kernel:?} at length {len}");
+            }
+        }
+    }
+}
+
+#[test]
+fn kernels_match_scalar() {
+    check::<f32>(1e-5);
+    check::<f64>(1e-13);
+
+    assert!(Kernel::best().is_available());
+}
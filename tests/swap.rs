@@ -0,0 +1,175 @@
+use convrs::{
+    conv::{ConvBuilder, ConvError, Scheduling},
+    swap::SwapConv,
+    upconv::FadeCurve,
+};
+
+#[test]
+fn swaps_fade_between_layouts() {
+    let filter = |len: usize, rate: f32| -> Vec<f32> {
+        (0..len)
+            .map(|i| (i as f32 * rate).sin() * (-(i as f32) / 400.0).exp())
+            .collect()
+    };
+    let signal: Vec<f32> = (0..4000).map(|i| (i as f32 * 0.031).sin()).collect();
+
+    // stereo, then mono in blocks of 100, which the old engine can't take,
+    // and with a different partition, but the same block size so the latency's the same
+    let old_builder = ConvBuilder::new(32)
+        .channels(2)
+        .scheduling(Scheduling::Offline);
+    let old_filter = vec![filter(1500, 0.1), filter(1500, 0.2)];
+    let new_builder = ConvBuilder::new(32)
+        .channels(1)
+        .partition(&[(32, 16), (256, 12)])
+        .max_block_size(100)
+        .scheduling(Scheduling::Offline);
+    let new_filter = vec![filter(2500, 0.05)];
+
+    let (mut swap, mut handle) = SwapConv::new(
+        old_builder.build(old_filter.clone()).unwrap(),
+        250,
+        FadeCurve::Linear,
+    );
+    let mut old = old_builder
+        .clone()
+        .max_block_size(100)
+        .build(old_filter)
+        .unwrap();
+    let mut new = new_builder.build(new_filter.clone()).unwrap();
+
+    let (before, after) = signal.split_at(32 * 20);
+    for block in before.chunks(32) {
+        let (mut l, mut r) = (block.to_vec(), block.to_vec());
+        swap.process_in_place(&mut [&mut l, &mut r]);
+        let _ = old.process([block, block].into_iter());
+    }
+
+    // a new block size would fade between two different points in the signal
+    assert_eq!(
+        handle.stage(
+            ConvBuilder::new(64)
+                .channels(1)
+                .build(new_filter.clone())
+                .unwrap()
+        ),
+        Err(ConvError::LatencyMismatch {
+            expected: 32,
+            actual: 64
+        })
+    );
+    handle
+        .stage(new_builder.build(new_filter.clone()).unwrap())
+        .unwrap();
+    assert_eq!(
+        handle.stage(new_builder.build(new_filter).unwrap()),
+        Err(ConvError::SwapPending)
+    );
+
+    for (i, block) in after.chunks(100).enumerate() {
+        let mut out = block.to_vec();
+        swap.process_in_place(&mut [&mut out]);
+        assert_eq!(swap.conv().routing().inputs(), 1);
+
+        let zeros = vec![0.0; block.len()];
+        let old_out = old.process([block, &zeros[..]].into_iter()).next().unwrap();
+        let new_out = new.process([block].into_iter()).next().unwrap();
+
+        // the old engine's left output fades out under the new engine's only one
+        for (j, o) in out.iter().enumerate() {
+            let position = ((i * 100 + j) as f32 / 250.0).min(1.0);
+            let expected = new_out[j] * position + old_out[j] * (1.0 - position);
+            assert!((o - expected).abs() < 1e-5, "block {i} sample {j}");
+        }
+        assert_eq!(swap.is_fading(), (i + 1) * 100 < 250);
+    }
+
+    // the new engine has been swapped in, so there's room to stage another
+    assert_eq!(
+        handle.stage(
+            ConvBuilder::new(32)
+                .channels(1)
+                .build(vec![vec![1.0]])
+                .unwrap()
+        ),
+        Ok(())
+    );
+}
+
+#[test]
+fn heads_change_the_latency_engines_are_staged_against() {
+    let filter = vec![(0..600).map(|i| 0.99f32.powi(i)).collect::<Vec<f32>>()];
+    let head = vec![0.5f32; 32];
+    let builder = ConvBuilder::new(32)
+        .channels(1)
+        .scheduling(Scheduling::Offline);
+
+    let (mut swap, mut handle) = SwapConv::new(
+        builder.build(filter.clone()).unwrap(),
+        64,
+        FadeCurve::Linear,
+    );
+    handle
+        .stage(builder.build(filter.clone()).unwrap())
+        .unwrap();
+
+    // the engine staged was checked against the latency without a head
+    assert_eq!(swap.set_head(&head), Err(ConvError::SwapPending));
+    let mut block = vec![0.0f32; 32];
+    while swap.is_fading() {
+        swap.process_in_place(&mut [&mut block]);
+    }
+    swap.set_head(&head).unwrap();
+    assert_eq!(swap.conv().latency(), 0);
+
+    assert_eq!(
+        handle.stage(builder.build(filter.clone()).unwrap()),
+        Err(ConvError::LatencyMismatch {
+            expected: 0,
+            actual: 32
+        })
+    );
+    let mut with_head = builder.build(filter).unwrap();
+    with_head.set_head(&head).unwrap();
+    assert_eq!(handle.stage(with_head), Ok(()));
+}
+
+#[test]
+fn fades_take_buffers_of_any_length() {
+    let filter = |rate: f32| -> Vec<Vec<f32>> {
+        vec![(0..800)
+            .map(|i| (i as f32 * rate).cos() * (-(i as f32) / 200.0).exp())
+            .collect()]
+    };
+    let signal: Vec<f32> = (0..3000).map(|i| (i as f32 * 0.043).sin()).collect();
+    let old_builder = ConvBuilder::new(32)
+        .channels(1)
+        .scheduling(Scheduling::Offline);
+    let new_builder = old_builder.clone().max_block_size(50);
+
+    let (mut swap, mut handle) = SwapConv::new(
+        old_builder.build(filter(0.1)).unwrap(),
+        400,
+        FadeCurve::Linear,
+    );
+    handle
+        .stage(new_builder.build(filter(0.3)).unwrap())
+        .unwrap();
+    let mut old = old_builder.build(filter(0.1)).unwrap();
+    let mut new = new_builder.build(filter(0.3)).unwrap();
+
+    // longer than either engine's max block size
+    for (i, buffer) in signal.chunks(130).enumerate() {
+        let mut out = buffer.to_vec();
+        swap.process_in_place(&mut [&mut out]);
+
+        let (mut old_out, mut new_out) = (buffer.to_vec(), buffer.to_vec());
+        old.process_in_place(&mut [&mut old_out]);
+        new.process_in_place(&mut [&mut new_out]);
+        for (j, o) in out.iter().enumerate() {
+            let position = ((i * 130 + j) as f32 / 400.0).min(1.0);
+            let expected = new_out[j] * position + old_out[j] * (1.0 - position);
+            assert!((o - expected).abs() < 1e-5, "buffer {i} sample {j}");
+        }
+    }
+}
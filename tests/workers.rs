@@ -0,0 +1,45 @@
+use convrs::conv::{ConvBuilder, Scheduling};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// on its own in here, so no other test's threads come and go while they're counted
+#[cfg(target_os = "linux")]
+#[test]
+fn dropping_a_conv_joins_its_workers() {
+    let threads = || std::fs::read_dir("/proc/self/task").unwrap().count();
+    let filter = vec![vec![0.5f32; 5000]];
+    let builder =
+        ConvBuilder::new(32)
+            .channels(1)
+            .partition(&[(32, 8), (128, 6), (512, 4), (2048, 2)]);
+
+    let before = threads();
+    let mut conv = builder
+        .clone()
+        .scheduling(Scheduling::Threaded)
+        .build(filter.clone())
+        .unwrap();
+    assert_eq!(threads(), before + 3);
+
+    // the queues only have room for the blocks a segment can have in flight
+    let offline = builder
+        .scheduling(Scheduling::Offline)
+        .build(filter)
+        .unwrap();
+    assert!(conv.memory_usage() < offline.memory_usage() * 2);
+
+    let block = [0.25f32; 32];
+    for _ in 0..100 {
+        let _ = conv.process_block([&block[..]].into_iter());
+    }
+    drop(conv);
+
+    // a joined thread can take a moment to be taken out of /proc
+    let start = Instant::now();
+    while threads() != before && start.elapsed() < Duration::from_secs(1) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(threads(), before);
+}
@@ -0,0 +1,63 @@
+use convrs::{
+    conv::{Conv, ConvError, Scheduling},
+    helpers::{process_filter, split_head},
+    upconv::{FadeCurve, Routing},
+};
+
+mod common;
+use common::direct_conv;
+
+#[test]
+fn head_has_no_latency() {
+    let filter: Vec<f32> = (0..1000)
+        .map(|i| (i as f32 * 0.37).sin() * (-(i as f32) / 300.0).exp())
+        .collect();
+    let signal: Vec<f32> = (0..3000).map(|i| (i as f32 * 0.11).sin()).collect();
+    let control = direct_conv(&signal, &filter);
+
+    for head_len in [32, 100] {
+        let (head, tail) = split_head(vec![filter.clone()], head_len, 32).unwrap();
+        let partition = &[(32, tail[0].len().div_ceil(32))];
+        let tail_processed = process_filter(tail, partition).unwrap();
+
+        let mut conv = Conv::new(
+            32,
+            &tail_processed,
+            Routing::Parallel(1),
+            64,
+            FadeCurve::default(),
+            Scheduling::default(),
+        )
+        .unwrap();
+        conv.set_head(&head).unwrap();
+
+        // odd block sizes, so the fifo in `process` is never lined up with the host
+        let mut test = vec![];
+        let mut done = 0;
+        for len in [1, 7, 64, 33, 5].into_iter().cycle() {
+            if done == signal.len() {
+                break;
+            }
+            let len = len.min(signal.len() - done);
+            let mut out = conv.process([&signal[done..done + len]].into_iter());
+            test.extend_from_slice(out.next().unwrap());
+            done += len;
+        }
+
+        for (t, c) in test.iter().zip(&control) {
+            assert!((t - c).abs() < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn head_has_to_cover_the_block() {
+    let filter = vec![vec![0.5f32; 100]];
+    assert_eq!(
+        split_head(filter, 16, 32).err(),
+        Some(ConvError::HeadTooShort {
+            head_len: 16,
+            block_size: 32
+        })
+    );
+}